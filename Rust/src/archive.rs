@@ -0,0 +1,715 @@
+use std::ffi::{OsStr, OsString};
+use std::fs::File;
+use std::io::{BufReader, Cursor, Read, Seek};
+use std::path::{Component, Path, PathBuf};
+
+use crate::FolderScan;
+use crate::cancel;
+use crate::comparer::FileData;
+use crate::events::{Event, EventKind};
+use crate::filedata::{FileDataCompareOption, FilePath, HashKey};
+use crate::progress::{Progress, SideProgress};
+use crate::scan::add_scanned;
+use crate::utils::{Config, format_bytes, hash_stream, hash_stream_git_blob, hash_stream_quick};
+
+/// Archives nested deeper than this are compared as plain files. This bounds recursion, not memory
+const MAX_ARCHIVE_DEPTH: usize = 8;
+
+/// A nested zip is read into memory to seek in it. Larger ones are compared as plain files
+#[cfg(not(test))]
+const MAX_NESTED_ZIP_BYTES: u64 = 64 * 1024 * 1024;
+
+/// Much smaller under test, so the cap can be reached without a 64 MiB archive
+#[cfg(test)]
+const MAX_NESTED_ZIP_BYTES: u64 = 64 * 1024;
+
+/// Separates an archive's path from the path of a member inside it, eg `dist/app.zip!/lib/x.dll`
+const MEMBER_SEPARATOR: &str = "!/";
+
+/// File name endings recognised as archives, lower case
+const ARCHIVE_SUFFIXES: [(&str, ArchiveKind); 6] = [
+    (".zip", ArchiveKind::Zip),
+    (".tar", ArchiveKind::Tar),
+    (".tar.gz", ArchiveKind::TarGz),
+    (".tgz", ArchiveKind::TarGz),
+    (".tar.zst", ArchiveKind::TarZst),
+    (".tzst", ArchiveKind::TarZst),
+];
+
+/// Archive formats that can be compared as if they were folders
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveKind {
+    Zip,
+    Tar,
+    TarGz,
+    TarZst,
+}
+
+impl ArchiveKind {
+    /// Recognise an archive from its file name
+    #[must_use]
+    pub fn from_name(name: &OsStr) -> Option<Self> {
+        let name = name.to_string_lossy().to_ascii_lowercase();
+        ARCHIVE_SUFFIXES
+            .iter()
+            .find(|(suffix, _)| name.ends_with(suffix))
+            .map(|&(_, kind)| kind)
+    }
+
+    /// Recognise an archive given on the command line. It must be an existing file, not a folder with an archive's name
+    pub fn from_path(path: &Path) -> Option<Self> {
+        if path.is_file() {
+            path.file_name().and_then(Self::from_name)
+        } else {
+            None
+        }
+    }
+}
+
+/// A regular file inside an archive, with its contents ready to stream
+struct Member<'a> {
+    path: PathBuf, // path of the archive, the separator, then the path inside the archive
+    size: u64,     // uncompressed size
+    reader: &'a mut dyn Read,
+}
+
+/// Collects the members of one archive, descending into nested archives if required
+struct ArchiveScanner<'a> {
+    config: &'a Config,
+    progress: &'a Progress,
+    counters: &'a SideProgress,
+    side: usize,
+    scan: FolderScan,
+}
+
+impl ArchiveScanner<'_> {
+    /// Key a member and add it to the scan. With `--descend-archives`, a nested archive is replaced by its members
+    fn add_member(&mut self, member: Member, depth: usize) -> anyhow::Result<()> {
+        self.progress.set_current(&member.path);
+
+        let nested = member.path.file_name().and_then(ArchiveKind::from_name);
+        if let Some(kind) = nested
+            && self.config.descend_archives
+            && depth < MAX_ARCHIVE_DEPTH
+        {
+            return match kind {
+                ArchiveKind::Zip => self.add_nested_zip(member, depth),
+                ArchiveKind::Tar | ArchiveKind::TarGz | ArchiveKind::TarZst => {
+                    self.add_nested_tar(kind, member, depth)
+                }
+            };
+        }
+
+        self.add_file(member.path, member.size, member.reader)
+    }
+
+    /// A zip needs to seek, so a nested one is read into memory, up to `MAX_NESTED_ZIP_BYTES`. A larger one, or
+    /// one that can't be read, is compared as a file
+    fn add_nested_zip(&mut self, member: Member, depth: usize) -> anyhow::Result<()> {
+        let mut contents = Vec::new();
+        (&mut *member.reader)
+            .take(MAX_NESTED_ZIP_BYTES + 1)
+            .read_to_end(&mut contents)?;
+        if u64::try_from(contents.len()).map_or(true, |len| len > MAX_NESTED_ZIP_BYTES) {
+            let message = format!(
+                "Archive '{}' is over {}, comparing it as a file",
+                member.path.display(),
+                format_bytes(MAX_NESTED_ZIP_BYTES)
+            );
+            self.config.warn(Some(self.side), &member.path, message);
+            let mut whole = contents.as_slice().chain(member.reader);
+            return self.add_file(member.path, member.size, &mut whole);
+        }
+
+        let mut inner = self.nested();
+        let result = visit_zip(
+            self.config,
+            self.side,
+            Cursor::new(&contents),
+            &member.path,
+            &mut |m| inner.add_member(m, depth + 1),
+        );
+        match result {
+            Ok(()) => {
+                self.scan.extend(inner.scan);
+                Ok(())
+            }
+            Err(e) if cancel::is_cancellation(&e) => Err(e),
+            Err(e) => {
+                let message = format!(
+                    "Cannot read archive '{}', comparing it as a file: {e}",
+                    member.path.display()
+                );
+                self.config.warn(Some(self.side), &member.path, message);
+                self.add_file(member.path, member.size, &mut contents.as_slice())
+            }
+        }
+    }
+
+    /// A tar is only read forwards, so a nested one is streamed straight from its parent. If it turns out not to
+    /// be readable its contents have been consumed by then, so it is kept as a file without them
+    fn add_nested_tar(
+        &mut self,
+        kind: ArchiveKind,
+        member: Member,
+        depth: usize,
+    ) -> anyhow::Result<()> {
+        let mut inner = self.nested();
+        let result = visit_tar_stream(
+            self.config,
+            self.side,
+            kind,
+            member.reader,
+            &member.path,
+            &mut |m| inner.add_member(m, depth + 1),
+        );
+        match result {
+            Ok(()) => {
+                self.scan.extend(inner.scan);
+                Ok(())
+            }
+            Err(e) if cancel::is_cancellation(&e) => Err(e),
+            Err(e) => {
+                let message = format!(
+                    "Cannot read archive '{}', comparing it without its contents: {e}",
+                    member.path.display()
+                );
+                self.config.warn(Some(self.side), &member.path, message);
+                let file = FileData {
+                    path: FilePath(member.path),
+                    size: member.size,
+                    contents: None,
+                };
+                add_scanned(self.config, &mut self.scan, file, self.side, self.counters);
+                Ok(())
+            }
+        }
+    }
+
+    /// A scanner for the members of a nested archive, which are added to this one's scan if it can be read
+    fn nested(&self) -> Self {
+        ArchiveScanner {
+            scan: FolderScan::new(self.config.file_comparer()),
+            ..*self
+        }
+    }
+
+    /// Key a member as an ordinary file
+    fn add_file(&mut self, path: PathBuf, size: u64, reader: &mut dyn Read) -> anyhow::Result<()> {
+        let contents = member_contents(self.config, size, reader, self.counters)?;
+        let file = FileData {
+            path: FilePath(path),
+            size,
+            contents,
+        };
+        add_scanned(self.config, &mut self.scan, file, self.side, self.counters);
+        Ok(())
+    }
+}
+
+/// Scan the members of an archive as if it were a folder. Each member is keyed exactly as a file on disk would be
+/// # Errors
+/// Will return an error if the archive cannot be read
+pub fn scan_archive(
+    config: &Config,
+    archive: &Path,
+    kind: ArchiveKind,
+    progress: &Progress,
+    side: usize,
+) -> anyhow::Result<FolderScan> {
+    let mut scanner = ArchiveScanner {
+        config,
+        progress,
+        counters: &progress.sides[side],
+        side,
+        scan: FolderScan::new(config.file_comparer()),
+    };
+    let file = BufReader::new(File::open(archive)?);
+
+    let result = visit_archive(config, side, kind, file, archive, &mut |member| {
+        scanner.add_member(member, 1)
+    });
+
+    // cancellation keeps what was scanned so far
+    match result {
+        Ok(()) => Ok(scanner.scan),
+        Err(e) if cancel::is_cancellation(&e) => Ok(scanner.scan),
+        Err(e) => Err(e.context(format!("Cannot read archive '{}'", archive.display()))),
+    }
+}
+
+/// Call `visit` for each regular file in the archive, in archive order
+fn visit_archive(
+    config: &Config,
+    side: usize,
+    kind: ArchiveKind,
+    reader: impl Read + Seek,
+    prefix: &Path,
+    visit: &mut dyn FnMut(Member) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    match kind {
+        ArchiveKind::Zip => visit_zip(config, side, reader, prefix, visit),
+        ArchiveKind::Tar | ArchiveKind::TarGz | ArchiveKind::TarZst => {
+            visit_tar_stream(config, side, kind, reader, prefix, visit)
+        }
+    }
+}
+
+/// Call `visit` for each regular file in a tar, tar.gz or tar.zst stream, which needs no seeking
+fn visit_tar_stream(
+    config: &Config,
+    side: usize,
+    kind: ArchiveKind,
+    reader: impl Read,
+    prefix: &Path,
+    visit: &mut dyn FnMut(Member) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    match kind {
+        ArchiveKind::Zip => Err(anyhow::anyhow!("A zip can't be read as a stream")),
+        ArchiveKind::Tar => visit_tar(config, side, reader, prefix, visit),
+        ArchiveKind::TarGz => visit_tar(
+            config,
+            side,
+            flate2::read::MultiGzDecoder::new(reader),
+            prefix,
+            visit,
+        ),
+        ArchiveKind::TarZst => {
+            let decoder = ruzstd::decoding::StreamingDecoder::new(reader)
+                .map_err(|e| anyhow::anyhow!("Invalid zstd stream: {e}"))?;
+            visit_tar(config, side, decoder, prefix, visit)
+        }
+    }
+}
+
+/// Visit the members of a zip file. Zip needs to seek to its central directory
+fn visit_zip(
+    config: &Config,
+    side: usize,
+    reader: impl Read + Seek,
+    prefix: &Path,
+    visit: &mut dyn FnMut(Member) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let mut zip = zip::ZipArchive::new(reader)?;
+
+    for index in 0..zip.len() {
+        config.cancel.check()?;
+        let mut file = zip.by_index(index)?;
+        if !file.is_file() {
+            continue;
+        }
+
+        // names that would escape the archive, like `../x`, can't be compared meaningfully
+        let Some(name) = file.enclosed_name() else {
+            config.emit(EventKind::WalkError, || Event::WalkError {
+                side,
+                path: Some(prefix.to_path_buf()),
+                message: format!("unsafe member name '{}'", file.name()),
+            });
+            continue;
+        };
+
+        visit(Member {
+            path: member_path(prefix, &name),
+            size: file.size(),
+            reader: &mut file,
+        })?;
+    }
+
+    Ok(())
+}
+
+/// Visit the members of a tar stream, which is read once from start to end
+fn visit_tar(
+    config: &Config,
+    side: usize,
+    reader: impl Read,
+    prefix: &Path,
+    visit: &mut dyn FnMut(Member) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let mut archive = tar::Archive::new(reader);
+
+    for entry in archive.entries()? {
+        config.cancel.check()?;
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+
+        // as in a zip, names that would escape the archive can't be compared meaningfully
+        let name = entry.path()?.into_owned();
+        if name
+            .components()
+            .any(|component| component == Component::ParentDir)
+        {
+            config.emit(EventKind::WalkError, || Event::WalkError {
+                side,
+                path: Some(prefix.to_path_buf()),
+                message: format!("unsafe member name '{}'", name.display()),
+            });
+            continue;
+        }
+
+        visit(Member {
+            path: member_path(prefix, &name),
+            size: entry.size(),
+            reader: &mut entry,
+        })?;
+    }
+
+    Ok(())
+}
+
+/// Build the path shown for a member, eg `release.tar.gz!/bin/app`. Leading `./` in tar names is dropped
+#[must_use]
+pub fn member_path(prefix: &Path, name: &Path) -> PathBuf {
+    let name: PathBuf = name
+        .components()
+        .filter(|component| !matches!(component, Component::CurDir | Component::RootDir))
+        .collect();
+
+    let mut path = OsString::from(prefix);
+    path.push(MEMBER_SEPARATOR);
+    path.push(name);
+    PathBuf::from(path)
+}
+
+/// Hash a member's contents if the comparison option reads them, matching `file_data` for the same file on disk
+/// # Errors
+/// Will return an error if the contents cannot be read
+pub fn member_contents(
+    config: &Config,
+    size: u64,
+    mut reader: &mut dyn Read,
+    counters: &SideProgress,
+) -> anyhow::Result<Option<HashKey>> {
+    let contents = match config.comparer {
+        FileDataCompareOption::Name
+        | FileDataCompareOption::NameSize
+        | FileDataCompareOption::Stem => return Ok(None),
+        FileDataCompareOption::Hash => hash_stream(
+            &mut reader,
+            config.algorithm,
+            Some(&counters.bytes),
+            &config.cancel,
+        )?,
+        FileDataCompareOption::QuickHash => hash_stream_quick(
+            &mut reader,
+            size,
+            config.algorithm,
+            config.quick_bytes,
+            Some(&counters.bytes),
+            &config.cancel,
+        )?,
+        FileDataCompareOption::GitBlob => {
+            hash_stream_git_blob(&mut reader, size, Some(&counters.bytes), &config.cancel)?
+        }
+    };
+
+    Ok(Some(contents))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::EventSink;
+    use crate::scan::compare;
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+
+    /// A fresh folder under the temp folder, removed when dropped
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let root = std::env::temp_dir().join(format!(
+                "folder_compare_archive_{name}_{}",
+                std::process::id()
+            ));
+            let _ = std::fs::remove_dir_all(&root);
+            std::fs::create_dir_all(&root).unwrap();
+            TempDir(root)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// Records every event it is sent
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<Event>>);
+
+    impl EventSink for Recorder {
+        fn event(&self, event: Event) {
+            self.0.lock().unwrap().push(event);
+        }
+    }
+
+    fn tar_bytes(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (name, contents) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            builder.append_data(&mut header, name, *contents).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    fn zip_bytes(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        let options = zip::write::SimpleFileOptions::default()
+            .compression_method(zip::CompressionMethod::Stored);
+        for (name, contents) in files {
+            writer.start_file(*name, options).unwrap();
+            writer.write_all(contents).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    /// An archive of the given kind holding the files, and the name it would have
+    fn archive_bytes(kind: ArchiveKind, files: &[(&str, &[u8])]) -> (Vec<u8>, &'static str) {
+        match kind {
+            ArchiveKind::Zip => (zip_bytes(files), "a.zip"),
+            ArchiveKind::Tar => (tar_bytes(files), "a.tar"),
+            ArchiveKind::TarGz => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+                encoder.write_all(&tar_bytes(files)).unwrap();
+                (encoder.finish().unwrap(), "a.tar.gz")
+            }
+            ArchiveKind::TarZst => (
+                ruzstd::encoding::compress_to_vec(
+                    tar_bytes(files).as_slice(),
+                    ruzstd::encoding::CompressionLevel::Fastest,
+                ),
+                "a.tar.zst",
+            ),
+        }
+    }
+
+    /// The paths of the members scanned from an archive, sorted
+    fn member_paths(config: &Config, archive: &Path) -> Vec<String> {
+        let kind = ArchiveKind::from_path(archive).unwrap();
+        let scan = scan_archive(config, archive, kind, &Progress::new(false, 2), 0).unwrap();
+        let mut paths: Vec<String> = scan
+            .iter()
+            .chain(&scan.duplicates)
+            .map(|file| file.path.0.to_string_lossy().replace('\\', "/"))
+            .collect();
+        paths.sort();
+        paths
+    }
+
+    #[test]
+    fn members_are_keyed_like_files_on_disk() {
+        let long: Vec<u8> = (0..=255).cycle().take(1000).collect();
+        let files: [(&str, &[u8]); 3] = [
+            ("dir/long.bin", &long),
+            ("short.txt", b"short"),
+            ("empty", b""),
+        ];
+
+        let temp = TempDir::new("keys");
+        let disk = temp.0.join("disk");
+        for (name, contents) in files {
+            let path = disk.join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        }
+        std::fs::write(disk.join("other.txt"), "only on disk").unwrap();
+
+        for kind in [
+            ArchiveKind::Zip,
+            ArchiveKind::Tar,
+            ArchiveKind::TarGz,
+            ArchiveKind::TarZst,
+        ] {
+            let (bytes, name) = archive_bytes(kind, &files);
+            let archive = temp.0.join(name);
+            std::fs::write(&archive, bytes).unwrap();
+
+            for comparer in [
+                FileDataCompareOption::Hash,
+                FileDataCompareOption::QuickHash,
+                FileDataCompareOption::GitBlob,
+            ] {
+                // a small quick hash, so the long file is hashed from each end
+                let config = Config {
+                    folder1: archive.clone(),
+                    folder2: disk.clone(),
+                    comparer,
+                    quick_bytes: 100,
+                    ..Config::default()
+                };
+                let result = compare(&config, &Progress::new(false, 2)).unwrap();
+                assert_eq!(result.matches().count(), 3, "{kind:?} {comparer:?}");
+                assert_eq!(result.difference_count(), 1, "{kind:?} {comparer:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn member_paths_follow_the_archive() {
+        let temp = TempDir::new("paths");
+        let archive = temp.0.join("release.tar");
+        let files: [(&str, &[u8]); 2] = [("./bin/app", b"app"), ("readme", b"readme")];
+        std::fs::write(&archive, tar_bytes(&files)).unwrap();
+
+        let root = archive.to_string_lossy().replace('\\', "/");
+        assert_eq!(
+            member_paths(&Config::default(), &archive),
+            [format!("{root}!/bin/app"), format!("{root}!/readme")]
+        );
+    }
+
+    #[test]
+    fn tar_members_escaping_the_archive_are_skipped() {
+        // the tar crate won't write `..`, so the name is set in the header directly
+        let mut builder = tar::Builder::new(Vec::new());
+        for (name, contents) in [(&b"../evil"[..], &b"evil"[..]), (b"fine", b"fine")] {
+            let mut header = tar::Header::new_old();
+            header.as_old_mut().name[..name.len()].copy_from_slice(name);
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append(&header, contents).unwrap();
+        }
+
+        let temp = TempDir::new("unsafe");
+        let archive = temp.0.join("a.tar");
+        std::fs::write(&archive, builder.into_inner().unwrap()).unwrap();
+
+        let recorder = Arc::new(Recorder::default());
+        let config = Config {
+            events: Some(recorder.clone()),
+            ..Config::default()
+        };
+        let root = archive.to_string_lossy().replace('\\', "/");
+        assert_eq!(member_paths(&config, &archive), [format!("{root}!/fine")]);
+
+        let events = recorder.0.lock().unwrap();
+        assert!(events.iter().any(|event| matches!(
+            event,
+            Event::WalkError { message, .. } if message.contains("../evil")
+        )));
+    }
+
+    /// A config that descends into nested archives, sending events to the recorder
+    fn descending(recorder: &Arc<Recorder>) -> Config {
+        Config {
+            descend_archives: true,
+            comparer: FileDataCompareOption::Hash,
+            events: Some(recorder.clone()),
+            ..Config::default()
+        }
+    }
+
+    fn warned(recorder: &Recorder, text: &str) -> bool {
+        recorder
+            .0
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|event| matches!(event, Event::Warning { message, .. } if message.contains(text)))
+    }
+
+    #[test]
+    fn nested_zip_over_the_cap_is_compared_as_a_file() {
+        let data = vec![7u8; usize::try_from(MAX_NESTED_ZIP_BYTES).unwrap() + 1];
+        let big = zip_bytes(&[("data.bin", &data)]);
+        let temp = TempDir::new("cap");
+        let archive = temp.0.join("outer.tar");
+        std::fs::write(&archive, tar_bytes(&[("big.zip", &big)])).unwrap();
+
+        let recorder = Arc::new(Recorder::default());
+        let config = descending(&recorder);
+        let scan = scan_archive(
+            &config,
+            &archive,
+            ArchiveKind::Tar,
+            &Progress::new(false, 2),
+            0,
+        )
+        .unwrap();
+        let files: Vec<&FileData> = scan.iter().collect();
+        assert_eq!(files.len(), 1);
+        assert!(files[0].path.0.ends_with("outer.tar!/big.zip"));
+        assert!(warned(&recorder, "is over"));
+
+        // every byte is hashed, including those read before the cap was reached
+        let whole = hash_stream(&mut big.as_slice(), config.algorithm, None, &config.cancel);
+        assert_eq!(files[0].contents, Some(whole.unwrap()));
+    }
+
+    /// A zip holding a chain of `levels` nested zips, the innermost holding `leaf.txt`
+    fn nested_zips(levels: usize) -> Vec<u8> {
+        let mut inner = zip_bytes(&[("leaf.txt", b"leaf")]);
+        for level in (1..=levels).rev() {
+            let name = format!("n{level}.zip");
+            inner = zip_bytes(&[(&name, &inner)]);
+        }
+        inner
+    }
+
+    #[test]
+    fn recursion_stops_at_the_depth_limit() {
+        let temp = TempDir::new("depth");
+        let recorder = Arc::new(Recorder::default());
+        let config = descending(&recorder);
+
+        let chain = |levels: usize| {
+            (1..=levels)
+                .map(|level| format!("!/n{level}.zip"))
+                .collect::<Vec<_>>()
+                .concat()
+        };
+
+        // the root and 7 nested zips make 8 levels, all expanded
+        let archive = temp.0.join("shallow.zip");
+        std::fs::write(&archive, nested_zips(MAX_ARCHIVE_DEPTH - 1)).unwrap();
+        let root = archive.to_string_lossy().replace('\\', "/");
+        assert_eq!(
+            member_paths(&config, &archive),
+            [format!("{root}{}!/leaf.txt", chain(MAX_ARCHIVE_DEPTH - 1))]
+        );
+
+        // one more, and the innermost zip is kept as a file
+        let archive = temp.0.join("deep.zip");
+        std::fs::write(&archive, nested_zips(MAX_ARCHIVE_DEPTH)).unwrap();
+        let root = archive.to_string_lossy().replace('\\', "/");
+        assert_eq!(
+            member_paths(&config, &archive),
+            [format!("{root}{}", chain(MAX_ARCHIVE_DEPTH))]
+        );
+    }
+
+    #[test]
+    fn nested_tar_members_are_streamed_with_their_paths() {
+        let (inner, _) = archive_bytes(ArchiveKind::TarGz, &[("./d/f.txt", b"f"), ("g.txt", b"g")]);
+        let temp = TempDir::new("nested_tar");
+        let archive = temp.0.join("root.zip");
+        std::fs::write(
+            &archive,
+            zip_bytes(&[("inner.tar.gz", &inner), ("bad.tar.gz", b"not gzip")]),
+        )
+        .unwrap();
+
+        let recorder = Arc::new(Recorder::default());
+        let root = archive.to_string_lossy().replace('\\', "/");
+        assert_eq!(
+            member_paths(&descending(&recorder), &archive),
+            [
+                format!("{root}!/bad.tar.gz"),
+                format!("{root}!/inner.tar.gz!/d/f.txt"),
+                format!("{root}!/inner.tar.gz!/g.txt"),
+            ]
+        );
+
+        // one that can't be read is kept as a file, and reported
+        assert!(warned(&recorder, "bad.tar.gz"));
+    }
+}
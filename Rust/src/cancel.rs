@@ -0,0 +1,76 @@
+use std::fmt::{Display, Formatter};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Exit status when the scan was interrupted and the results are incomplete
+pub const EXIT_INCOMPLETE: i32 = 3;
+
+/// Asks one scan, and anything acting on its results, to stop early. Clones share the flag, so a clone kept by
+/// the caller or a signal handler cancels the scan holding the other. Polled by the scanning and hashing loops
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    /// A token that hasn't been cancelled
+    #[must_use]
+    pub fn new() -> Self {
+        CancelToken::default()
+    }
+
+    /// Ask the scan to stop. It returns what it found so far, marked incomplete
+    pub fn request(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Has cancellation been requested?
+    #[inline]
+    #[must_use]
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Return a `Cancelled` error if cancellation has been requested
+    /// # Errors
+    /// Will return `Cancelled` once cancellation has been requested
+    #[inline]
+    pub fn check(&self) -> anyhow::Result<()> {
+        if self.is_cancelled() {
+            Err(Cancelled.into())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Error returned by work that stopped early because cancellation was requested
+#[derive(Debug)]
+pub struct Cancelled;
+
+impl Display for Cancelled {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// Install a SIGINT/SIGTERM (Ctrl-C on Windows) handler that cancels the token, asking workers to stop
+/// cooperatively. A second signal exits immediately, in case something is stuck. Only one handler can be
+/// installed per process, so this is for programs rather than the library
+/// # Errors
+/// Will return an error if the handler cannot be installed
+pub fn install_handler(token: CancelToken) -> anyhow::Result<()> {
+    ctrlc::set_handler(move || {
+        if token.0.swap(true, Ordering::Relaxed) {
+            std::process::exit(EXIT_INCOMPLETE);
+        }
+        eprintln!("\nCancelling, finishing with partial results...");
+    })?;
+    Ok(())
+}
+
+/// Was this error caused by cancellation?
+#[must_use]
+pub fn is_cancellation(err: &anyhow::Error) -> bool {
+    err.is::<Cancelled>()
+}
@@ -0,0 +1,528 @@
+use git_version::git_version;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use folder_compare::cancel::CancelToken;
+use folder_compare::comparer::NameTransform;
+use folder_compare::filelist::STDIN_LIST;
+use folder_compare::meta;
+use folder_compare::utils::{DEFAULT_QUICK_KIB, VERSION};
+use folder_compare::{
+    ColorMode, Config, DedupeMode, Event, EventKind, EventSink, FileDataCompareOption, GitSource,
+    HashAlgorithm, MetaField, NameCase, NameCheckScope, NameNormalize, ScriptFormat, SyncPolicy,
+    parse_algorithm, parse_comparer, parse_io_strategy, parse_meta_fields, parse_name_case,
+    parse_name_normalize, parse_policy,
+};
+
+pub const GIT_VERSION: &str = git_version!(args = ["--abbrev=40", "--always", "--dirty=+"]);
+
+pub const HELP: &str = "\
+USAGE:
+    folder_compare -a <folder> -b <folder> [-c <comparison>] [-r] [-f]
+    folder_compare -a <folder> [-b <folder>] --check-names <scope>
+
+MANDATORY PARAMETERS:
+    -a, --foldera                First folder, archive or git:<repo>@<rev> to compare
+    -b, --folderb                Second folder, archive or git:<repo>@<rev> to compare
+
+OPTIONS:
+    --folder [folder]            A further folder to compare, can be repeated. Shows a presence matrix
+    -c, --comparison [value]     Comparison to use.
+    -r, --raw                    Raw output, for piping. Never coloured, and paths are always full
+    --color [value]              Colour the output, auto, always or never. Default is auto, which respects NO_COLOR
+    --full-paths                 Show full paths, rather than paths relative to the folder
+    -o, --one-thread             Only use one thread, don't scan folders in parallel
+    -f, --first-only             Only show files in folder A missing from folder B (default is both)
+    --expand                     List every file inside a folder only on one side, not just the folder
+    --raw-folders                With --raw, list folders only on one side instead of the files inside them
+    --tree                       Show the differences as an indented tree of folders, with file counts and sizes
+    --report [file]              Also write a self-contained HTML report of the differences, eg report.html
+    -q, --quick-kib [value]      KiB read from each end of a file for QuickHash. Default is 64
+    --verify                     Fully hash files that match on QuickHash, to confirm the match
+    -g, --algorithm [value]      Hash algorithm to use.
+    -i, --io [value]             I/O strategy for hashing, Auto, Buffered or Large. Default is Auto
+    --copy-missing-to-b          Copy files only in folder A into folder B, at the same relative path
+    --copy-missing-to-a          Copy files only in folder B into folder A, at the same relative path
+    --dry-run                    Log the copy, mirror or dedupe actions that would be made, without changing anything
+    --emit-script [value]        Write a script of sync actions to stdout instead of the results
+    --policy [value]             Sync policy for --emit-script.
+    --mirror                     Make folder B match folder A: copy, replace changed files, delete extras
+    --quarantine [folder]        Where mirror moves deleted and replaced files. Default is <B>.quarantine
+    --no-quarantine              Mirror deletes files permanently instead of quarantining them
+    --max-delete [value]         Abort a mirror or script that would delete more than this many files
+    -y, --yes                    Don't ask for confirmation before mirroring
+    --dedupe [value]             Replace identical files with links (Hardlink or Reflink). Needs Hash comparison
+    --descend-archives           Compare the members of zip and tar files found in the folders, not the archives
+    --list-a [file]              Compare the files in this list instead of walking folder A. Use - for stdin
+    --list-b [file]              Compare the files in this list instead of walking folder B
+    --name-case [value]          Whether case matters in names and relative paths, sensitive or insensitive
+    --name-normalize [value]     Unicode normalisation of names and relative paths, none, nfc, nfd or nfkc
+    --name-transform [s/re/to/]  Regex substitution applied to names before comparing, can be repeated
+    --check-names [scope]        Report names that clash when case and Unicode form are ignored, or that
+                                 are invalid on Windows, instead of comparing. -b is optional
+    --check-meta [fields]        Also compare metadata of files at the same relative path. Comma separated
+    --mtime-tolerance [secs]     Modification times this close are equal, eg 2 for FAT. Default is 0
+    
+Comparison can be:
+    Name, NameSize, Stem, Hash, QuickHash or GitBlob. Default is Name.
+
+Algorithm can be:
+    BLAKE3, SHA256, SHA512, SHA1, MD5 or XXH3. Default is SHA256.
+
+Script can be:
+    sh (POSIX shell).
+
+Policy can be:
+    copy-missing, mirror or delete-extra. Default is copy-missing. Folder B is always the one changed.
+
+Name check scope can be:
+    tree (each folder on its own) or union (all the folders together, as if copied into one).
+
+Metadata fields can be:
+    mode, uid, gid, mtime, xattr or acl (Linux only).";
+
+/// Parse an optional enum argument, with a friendly message when the value is not recognised
+fn parse_optional<T: FromStr>(value: Option<String>, message: &str) -> anyhow::Result<Option<T>> {
+    value
+        .map(|s| T::from_str(&s).map_err(|_| anyhow::anyhow!("{message}")))
+        .transpose()
+}
+
+/// Make a root absolute. A `git:<repo>@<rev>` root keeps its form, with the repository path made absolute
+fn parse_root(root: &str) -> anyhow::Result<PathBuf> {
+    match GitSource::parse(root) {
+        Some(source) => source.canonical_root(),
+        None => Ok(Path::new(root).canonicalize()?),
+    }
+}
+
+/// Show the version and commit the program was built from
+fn print_banner() {
+    println!(
+        "Folder_comparer Rust, ver: {}, commit: {}",
+        VERSION.unwrap_or("?"),
+        GIT_VERSION
+    );
+    println!();
+}
+
+/// The library reports what it skipped or worked around as events, which are printed on stderr. The rest of
+/// the results are shown once the comparison is complete, so no other events are asked for
+struct ProblemPrinter;
+
+impl EventSink for ProblemPrinter {
+    fn event(&self, event: Event) {
+        match event {
+            Event::WalkError {
+                path: Some(path),
+                message,
+                ..
+            } => eprintln!("Skipping '{}': {message}", path.display()),
+            Event::WalkError {
+                path: None,
+                message,
+                ..
+            } => eprintln!("Skipping: {message}"),
+            Event::Warning { message, .. } => eprintln!("{message}"),
+            _ => {}
+        }
+    }
+
+    fn wants(&self, kind: EventKind) -> bool {
+        matches!(kind, EventKind::WalkError | EventKind::Warning)
+    }
+}
+
+/// Make each of several roots absolute
+fn parse_roots(roots: &[String]) -> anyhow::Result<Vec<PathBuf>> {
+    roots.iter().map(|root| parse_root(root)).collect()
+}
+
+pub fn parse_args() -> anyhow::Result<Config> {
+    let mut pargs = pico_args::Arguments::from_env();
+
+    // a script goes to stdout, so nothing else can be printed there
+    let emit_script_str: Option<String> = pargs.opt_value_from_str("--emit-script")?;
+    let raw = pargs.contains(["-r", "--raw"]) || emit_script_str.is_some();
+    if !raw {
+        print_banner();
+    }
+
+    if pargs.contains(["-h", "--help"]) {
+        println!("{HELP}");
+        return Err(anyhow::anyhow!("Exiting early"));
+    }
+
+    let path1: String = pargs.value_from_str(["-a", "--foldera"])?;
+    let path2: Option<String> = pargs.opt_value_from_str(["-b", "--folderb"])?;
+    let extra_paths: Vec<String> = pargs.values_from_str("--folder")?;
+    let comparer_str: Option<String> = pargs.opt_value_from_str(["-c", "--comparison"])?;
+    let quick_kib: Option<u64> = pargs.opt_value_from_str(["-q", "--quick-kib"])?;
+    let algorithm_str: Option<String> = pargs.opt_value_from_str(["-g", "--algorithm"])?;
+    let io_str: Option<String> = pargs.opt_value_from_str(["-i", "--io"])?;
+    let policy_str: Option<String> = pargs.opt_value_from_str("--policy")?;
+    let dedupe_str: Option<String> = pargs.opt_value_from_str("--dedupe")?;
+    let check_names_str: Option<String> = pargs.opt_value_from_str("--check-names")?;
+    let color_str: Option<String> = pargs.opt_value_from_str("--color")?;
+
+    // additional validation
+
+    let comparer = parse_comparer(comparer_str.as_ref()).map_err(|_| {
+        anyhow::anyhow!("Comparison should be Name, NameSize, Stem, Hash, QuickHash or GitBlob")
+    })?;
+    let algorithm = parse_algorithm(algorithm_str.as_ref()).map_err(|_| {
+        anyhow::anyhow!("Algorithm should be BLAKE3, SHA256, SHA512, SHA1, MD5 or XXH3")
+    })?;
+    let io = parse_io_strategy(io_str.as_ref())
+        .map_err(|_| anyhow::anyhow!("I/O strategy should be Auto, Buffered or Large"))?;
+    let policy = parse_policy(policy_str.as_ref())
+        .map_err(|_| anyhow::anyhow!("Policy should be copy-missing, mirror or delete-extra"))?;
+    let emit_script: Option<ScriptFormat> =
+        parse_optional(emit_script_str, "Script format should be sh")?;
+    let dedupe: Option<DedupeMode> =
+        parse_optional(dedupe_str, "Dedupe should be Hardlink or Reflink")?;
+    let check_names: Option<NameCheckScope> =
+        parse_optional(check_names_str, "Name check should be tree or union")?;
+    let color: Option<ColorMode> =
+        parse_optional(color_str, "Colour should be auto, always or never")?;
+
+    let path2 = folder_b(&path1, path2, check_names.is_some())?;
+
+    validate_arguments(
+        comparer,
+        quick_kib,
+        algorithm_str.is_some(),
+        policy_str.is_some() && emit_script.is_none(),
+    )?;
+
+    let (name_case, name_normalize, name_transforms) = parse_name_options(&mut pargs)?;
+    let (check_meta, mtime_tolerance) = parse_meta_options(&mut pargs)?;
+
+    // package the config options, so they can be easily passed around
+
+    let config = Config {
+        folder1: parse_root(&path1)?,
+        folder2: parse_root(&path2)?,
+        extra_folders: parse_roots(&extra_paths)?,
+        comparer,
+        // git's blob ids are always SHA-1
+        algorithm: if comparer == FileDataCompareOption::GitBlob {
+            HashAlgorithm::Sha1
+        } else {
+            algorithm
+        },
+        io,
+        raw,
+        color: color.unwrap_or_default(),
+        full_paths: pargs.contains("--full-paths"),
+        first_only: pargs.contains(["-f", "--first-only"]),
+        expand: pargs.contains("--expand"),
+        raw_folders: pargs.contains("--raw-folders"),
+        tree: pargs.contains("--tree"),
+        report: pargs.opt_value_from_str("--report")?,
+        one_thread: pargs.contains(["-o", "--one-thread"]),
+        quick_bytes: quick_kib.unwrap_or(DEFAULT_QUICK_KIB).saturating_mul(1024),
+        verify: pargs.contains("--verify"),
+        copy_missing_to_b: pargs.contains("--copy-missing-to-b"),
+        copy_missing_to_a: pargs.contains("--copy-missing-to-a"),
+        dry_run: pargs.contains("--dry-run"),
+        emit_script,
+        policy,
+        mirror: pargs.contains("--mirror"),
+        quarantine: pargs.opt_value_from_str("--quarantine")?,
+        no_quarantine: pargs.contains("--no-quarantine"),
+        max_delete: pargs.opt_value_from_str("--max-delete")?,
+        yes: pargs.contains(["-y", "--yes"]),
+        dedupe,
+        descend_archives: pargs.contains("--descend-archives"),
+        list_a: pargs.opt_value_from_str("--list-a")?,
+        list_b: pargs.opt_value_from_str("--list-b")?,
+        name_case,
+        name_normalize,
+        name_transforms,
+        check_names,
+        check_meta,
+        mtime_tolerance,
+        custom_comparer: None,
+        events: Some(Arc::new(ProblemPrinter)),
+        cancel: CancelToken::new(),
+    };
+
+    validate_config(&config)?;
+
+    // Check for unused arguments, and error out if there are any
+    let unused = pargs.finish();
+    if !unused.is_empty() {
+        return Err(anyhow::anyhow!("Unused arguments: {unused:?}"));
+    }
+
+    Ok(config)
+}
+
+/// Folder B, which is optional when checking names. A single tree is then given as both A and B
+fn folder_b(path1: &str, path2: Option<String>, checking_names: bool) -> anyhow::Result<String> {
+    match path2 {
+        Some(path2) => Ok(path2),
+        None if checking_names => Ok(path1.to_string()),
+        None => Err(anyhow::anyhow!("Folder B (-b) must be given")),
+    }
+}
+
+/// Parse how names are rewritten and folded before comparing them
+fn parse_name_options(
+    pargs: &mut pico_args::Arguments,
+) -> anyhow::Result<(NameCase, NameNormalize, Vec<NameTransform>)> {
+    let case_str: Option<String> = pargs.opt_value_from_str("--name-case")?;
+    let normalize_str: Option<String> = pargs.opt_value_from_str("--name-normalize")?;
+    let transform_strs: Vec<String> = pargs.values_from_str("--name-transform")?;
+
+    let case = parse_name_case(case_str.as_ref())
+        .map_err(|_| anyhow::anyhow!("Name case should be sensitive or insensitive"))?;
+    let normalize = parse_name_normalize(normalize_str.as_ref())
+        .map_err(|_| anyhow::anyhow!("Name normalisation should be none, nfc, nfd or nfkc"))?;
+    let transforms = transform_strs
+        .iter()
+        .map(|text| NameTransform::parse(text))
+        .collect::<anyhow::Result<_>>()?;
+    Ok((case, normalize, transforms))
+}
+
+/// Parse which metadata is compared, and how far apart modification times can be
+fn parse_meta_options(
+    pargs: &mut pico_args::Arguments,
+) -> anyhow::Result<(Vec<MetaField>, Duration)> {
+    let fields_str: Option<String> = pargs.opt_value_from_str("--check-meta")?;
+    let tolerance: Option<f64> = pargs.opt_value_from_str("--mtime-tolerance")?;
+
+    let fields = match fields_str {
+        Some(s) => parse_meta_fields(&s).map_err(|_| {
+            anyhow::anyhow!("Metadata fields should be mode, uid, gid, mtime, xattr or acl")
+        })?,
+        None => Vec::new(),
+    };
+    if let Some(&field) = fields.iter().find(|&&field| !meta::supported(field)) {
+        return Err(anyhow::anyhow!(
+            "--check-meta {field:?} is not supported on this platform"
+        ));
+    }
+
+    if tolerance.is_some() && !fields.contains(&MetaField::Mtime) {
+        return Err(anyhow::anyhow!(
+            "--mtime-tolerance only applies to --check-meta with mtime"
+        ));
+    }
+    let tolerance = Duration::try_from_secs_f64(tolerance.unwrap_or(0.0))
+        .map_err(|_| anyhow::anyhow!("--mtime-tolerance must be a number of seconds, 0 or more"))?;
+    Ok((fields, tolerance))
+}
+
+/// Check options whose validity depends on whether they were given at all, which `Config` doesn't record
+fn validate_arguments(
+    comparer: FileDataCompareOption,
+    quick_kib: Option<u64>,
+    algorithm_given: bool,
+    policy_without_script: bool,
+) -> anyhow::Result<()> {
+    if comparer != FileDataCompareOption::QuickHash && quick_kib.is_some() {
+        return Err(anyhow::anyhow!(
+            "--quick-kib can only be used with QuickHash comparison"
+        ));
+    }
+
+    if quick_kib == Some(0) {
+        return Err(anyhow::anyhow!("--quick-kib must be greater than zero"));
+    }
+
+    if comparer == FileDataCompareOption::GitBlob && algorithm_given {
+        return Err(anyhow::anyhow!(
+            "GitBlob comparison always uses SHA1, so can't be used with --algorithm"
+        ));
+    }
+
+    if policy_without_script {
+        return Err(anyhow::anyhow!("--policy only applies to --emit-script"));
+    }
+
+    Ok(())
+}
+
+/// Check for combinations of options that don't make sense together
+fn validate_config(config: &Config) -> anyhow::Result<()> {
+    if config.verify && config.comparer != FileDataCompareOption::QuickHash {
+        return Err(anyhow::anyhow!(
+            "--verify can only be used with QuickHash comparison"
+        ));
+    }
+
+    let copying = config.copy_missing_to_a || config.copy_missing_to_b;
+
+    if config.emit_script.is_some() && (copying || config.mirror) {
+        return Err(anyhow::anyhow!(
+            "--emit-script writes a script instead of changing files, so can't be combined with --copy-missing-to-a, --copy-missing-to-b or --mirror"
+        ));
+    }
+
+    if config.mirror && copying {
+        return Err(anyhow::anyhow!(
+            "--mirror already copies missing files, so can't be combined with --copy-missing-to-a or --copy-missing-to-b"
+        ));
+    }
+
+    if !config.mirror && (config.quarantine.is_some() || config.no_quarantine || config.yes) {
+        return Err(anyhow::anyhow!(
+            "--quarantine, --no-quarantine and --yes only apply to --mirror"
+        ));
+    }
+
+    if config.quarantine.is_some() && config.no_quarantine {
+        return Err(anyhow::anyhow!(
+            "--quarantine and --no-quarantine can't be used together"
+        ));
+    }
+
+    if config.max_delete.is_some() && !config.mirror && config.emit_script.is_none() {
+        return Err(anyhow::anyhow!(
+            "--max-delete only applies to --mirror or --emit-script"
+        ));
+    }
+
+    if config.emit_script.is_some() && config.policy == SyncPolicy::DeleteExtra && config.first_only
+    {
+        return Err(anyhow::anyhow!(
+            "The delete-extra policy needs the files in folder B, so can't be used with --first-only"
+        ));
+    }
+
+    if config.copy_missing_to_a && config.first_only {
+        return Err(anyhow::anyhow!(
+            "--copy-missing-to-a needs the files in folder B, so can't be used with --first-only"
+        ));
+    }
+
+    if config.dedupe.is_some() && config.comparer != FileDataCompareOption::Hash {
+        return Err(anyhow::anyhow!(
+            "--dedupe needs identical contents, so can only be used with Hash comparison"
+        ));
+    }
+
+    let changes_files =
+        copying || config.mirror || config.emit_script.is_some() || config.dedupe.is_some();
+    if (config.has_virtual_root() || config.descend_archives) && (changes_files || config.verify) {
+        return Err(anyhow::anyhow!(
+            "Archives and git revisions can only be compared, so can't be used with --verify, copying, mirroring, scripts or dedupe"
+        ));
+    }
+
+    if !config.extra_folders.is_empty() && (changes_files || config.first_only || config.verify) {
+        return Err(anyhow::anyhow!(
+            "--first-only, --verify, copying, mirroring, scripts and dedupe only apply when comparing two folders"
+        ));
+    }
+
+    if config.dedupe.is_some() && config.emit_script.is_some() {
+        return Err(anyhow::anyhow!(
+            "--dedupe can't be combined with --emit-script"
+        ));
+    }
+
+    if config.check_names.is_some()
+        && (changes_files
+            || config.first_only
+            || config.verify
+            || config.comparer != FileDataCompareOption::Name)
+    {
+        return Err(anyhow::anyhow!(
+            "--check-names only checks names, so can't be used with other comparisons, --first-only, --verify, copying, mirroring, scripts or dedupe"
+        ));
+    }
+
+    if !config.name_transforms.is_empty() && config.reads_contents() {
+        return Err(anyhow::anyhow!(
+            "--name-transform only applies to Name, NameSize and Stem comparisons"
+        ));
+    }
+
+    validate_reports(config)?;
+
+    validate_lists(config)?;
+
+    if config.dry_run && !copying && !config.mirror && config.dedupe.is_none() {
+        return Err(anyhow::anyhow!(
+            "--dry-run only applies to --copy-missing-to-a, --copy-missing-to-b, --mirror or --dedupe"
+        ));
+    }
+
+    Ok(())
+}
+
+/// Check the options that add to the report of a comparison: metadata, the tree and the HTML report
+fn validate_reports(config: &Config) -> anyhow::Result<()> {
+    if !config.check_meta.is_empty()
+        && (config.has_virtual_root()
+            || config.descend_archives
+            || !config.extra_folders.is_empty()
+            || config.emit_script.is_some()
+            || config.check_names.is_some())
+    {
+        return Err(anyhow::anyhow!(
+            "--check-meta compares files on disk in two folders, so can't be used with archives, git revisions, --folder, scripts or --check-names"
+        ));
+    }
+
+    if config.raw_folders && (!config.raw || config.emit_script.is_some()) {
+        return Err(anyhow::anyhow!("--raw-folders can only be used with --raw"));
+    }
+
+    if config.tree && config.raw {
+        return Err(anyhow::anyhow!(
+            "--tree is for reading, so can't be used with --raw or --emit-script"
+        ));
+    }
+
+    if config.report.is_some()
+        && (!config.extra_folders.is_empty()
+            || config.emit_script.is_some()
+            || config.check_names.is_some())
+    {
+        return Err(anyhow::anyhow!(
+            "--report describes a comparison of two folders, so can't be used with --folder, scripts or --check-names"
+        ));
+    }
+
+    Ok(())
+}
+
+/// Check that file lists are given for real folders, and not both on stdin
+fn validate_lists(config: &Config) -> anyhow::Result<()> {
+    let stdin = Path::new(STDIN_LIST);
+    if config.list_a.as_deref() == Some(stdin) && config.list_b.as_deref() == Some(stdin) {
+        return Err(anyhow::anyhow!(
+            "Only one of --list-a and --list-b can be read from stdin"
+        ));
+    }
+
+    for (list, root) in [
+        (&config.list_a, &config.folder1),
+        (&config.list_b, &config.folder2),
+    ] {
+        if list.is_some() && !root.is_dir() {
+            return Err(anyhow::anyhow!(
+                "A file list replaces walking a folder, so '{}' must be a folder",
+                root.display()
+            ));
+        }
+    }
+
+    // a mirror acts on everything under B, not just the listed files
+    let mirroring =
+        config.mirror || (config.emit_script.is_some() && config.policy == SyncPolicy::Mirror);
+    if mirroring && (config.list_a.is_some() || config.list_b.is_some()) {
+        return Err(anyhow::anyhow!(
+            "--list-a and --list-b can't be used with mirroring"
+        ));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,430 @@
+use regex::Regex;
+use std::borrow::Cow;
+use std::collections::hash_map::DefaultHasher;
+use std::ffi::{OsStr, OsString};
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use unicode_normalization::UnicodeNormalization;
+
+use crate::filedata::{FileDataCompareOption, FilePath, HashKey, NameCase, NameNormalize};
+
+/// Trait for equality and hashing functions, like C#'s `IEqualityComparer<T>`. Files are matched by the comparer
+/// rather than by `Eq` and `Hash` on the file itself, so any rule can be plugged in. Files that are equal must
+/// have equal hashes
+pub trait Comparer<T>: Send + Sync {
+    fn equals(&self, a: &T, b: &T) -> bool;
+    fn hash(&self, value: &T) -> usize;
+}
+
+/// A comparer for scanned files, shared by both sides of a comparison and the scanning threads
+pub type FileComparer = Arc<dyn Comparer<FileData>>;
+
+/// A scanned file, as seen by a comparer
+#[derive(Debug, Clone)]
+pub struct FileData {
+    pub path: FilePath, // path shown in results, including any archive or git prefix
+    pub size: u64,      // size in bytes, uncompressed for archive members
+    pub contents: Option<HashKey>, // hash of the contents, only for comparisons that read them
+}
+
+impl FileData {
+    /// The file name, without any folders
+    #[must_use]
+    pub fn name(&self) -> &OsStr {
+        self.path.0.file_name().unwrap_or_default()
+    }
+}
+
+/// How names are folded before they are compared, from `--name-case` and `--name-normalize`
+#[derive(Debug, Copy, Clone, Default)]
+pub struct NameForm {
+    pub case: NameCase,
+    pub normalize: NameNormalize,
+}
+
+impl NameForm {
+    /// The name as it is compared. Borrowed when nothing is folded. Names that aren't valid Unicode can't be
+    /// normalised, and only have ASCII letters folded
+    #[must_use]
+    pub fn apply<'a>(&self, name: &'a OsStr) -> Cow<'a, OsStr> {
+        if self.case == NameCase::Sensitive && self.normalize == NameNormalize::None {
+            return Cow::Borrowed(name);
+        }
+
+        let Some(text) = name.to_str() else {
+            return match self.case {
+                NameCase::Sensitive => Cow::Borrowed(name),
+                NameCase::Insensitive => Cow::Owned(name.to_ascii_lowercase()),
+            };
+        };
+
+        let text = match self.case {
+            NameCase::Sensitive => Cow::Borrowed(text),
+            NameCase::Insensitive => Cow::Owned(text.to_lowercase()),
+        };
+        let text: String = match self.normalize {
+            NameNormalize::None => text.into_owned(),
+            NameNormalize::Nfc => text.nfc().collect(),
+            NameNormalize::Nfd => text.nfd().collect(),
+            NameNormalize::Nfkc => text.nfkc().collect(),
+        };
+        Cow::Owned(OsString::from(text))
+    }
+
+    /// Fold every component of a relative path, so paths can be matched the way names are
+    #[must_use]
+    pub fn apply_path(&self, path: &Path) -> PathBuf {
+        path.iter().map(|component| self.apply(component)).collect()
+    }
+}
+
+/// A regex substitution applied to names before they are compared, from `--name-transform 's/regex/replacement/'`
+#[derive(Debug, Clone)]
+pub struct NameTransform {
+    pub pattern: Regex,
+    pub replacement: String, // may refer to groups, eg $1
+    pub all: bool,           // replace every match, rather than the first, from the g flag
+}
+
+impl NameTransform {
+    /// Parse a sed style substitution, `s/regex/replacement/` with an optional `g` flag. Any character can
+    /// replace the `/`, and a backslash before it makes it literal
+    /// # Errors
+    /// Will return an error if the substitution is malformed or the regex is invalid
+    pub fn parse(text: &str) -> anyhow::Result<Self> {
+        let malformed = || {
+            anyhow::anyhow!("Name transform should look like s/regex/replacement/, found '{text}'")
+        };
+
+        let rest = text.strip_prefix('s').ok_or_else(malformed)?;
+        let delimiter = rest.chars().next().ok_or_else(malformed)?;
+        let parts = split_unescaped(&rest[delimiter.len_utf8()..], delimiter);
+        let [pattern, replacement, flags] = &parts[..] else {
+            return Err(malformed());
+        };
+        if !flags.is_empty() && flags != "g" {
+            return Err(anyhow::anyhow!(
+                "Name transform flags can only be g, found '{flags}'"
+            ));
+        }
+
+        Ok(NameTransform {
+            pattern: Regex::new(pattern)
+                .map_err(|e| anyhow::anyhow!("Invalid name transform regex: {e}"))?,
+            replacement: replacement.clone(),
+            all: flags == "g",
+        })
+    }
+
+    /// Apply the substitution to a name
+    #[must_use]
+    pub fn apply<'a>(&self, name: &'a str) -> Cow<'a, str> {
+        if self.all {
+            self.pattern.replace_all(name, self.replacement.as_str())
+        } else {
+            self.pattern.replace(name, self.replacement.as_str())
+        }
+    }
+}
+
+/// Split at each delimiter not preceded by a backslash, dropping the backslash before an escaped delimiter
+fn split_unescaped(text: &str, delimiter: char) -> Vec<String> {
+    let mut parts = vec![String::new()];
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        let part = parts.last_mut().expect("parts is never empty");
+        if c == '\\' && chars.peek() == Some(&delimiter) {
+            part.push(delimiter);
+            chars.next();
+        } else if c == delimiter {
+            parts.push(String::new());
+        } else {
+            part.push(c);
+        }
+    }
+    parts
+}
+
+/// Everything done to a name before it is compared: the transforms in order, then case and Unicode folding
+#[derive(Debug, Clone, Default)]
+pub struct NameKey {
+    pub transforms: Vec<NameTransform>,
+    pub form: NameForm,
+}
+
+impl NameKey {
+    /// The name as it is compared. Names that aren't valid Unicode can't be transformed, only folded
+    #[must_use]
+    pub fn apply<'a>(&self, name: &'a OsStr) -> Cow<'a, OsStr> {
+        let transformed = match name.to_str() {
+            Some(text) if !self.transforms.is_empty() => {
+                let text = self
+                    .transforms
+                    .iter()
+                    .fold(Cow::Borrowed(text), |text, transform| {
+                        Cow::Owned(transform.apply(&text).into_owned())
+                    });
+                match text {
+                    Cow::Borrowed(text) => Cow::Borrowed(OsStr::new(text)),
+                    Cow::Owned(text) => Cow::Owned(OsString::from(text)),
+                }
+            }
+            _ => Cow::Borrowed(name),
+        };
+
+        match transformed {
+            Cow::Borrowed(name) => self.form.apply(name),
+            Cow::Owned(name) => Cow::Owned(self.form.apply(&name).into_owned()),
+        }
+    }
+
+    /// The stem of the name as it is compared, ie without its last extension
+    #[must_use]
+    pub fn apply_stem(&self, name: &OsStr) -> OsString {
+        let name = self.apply(name);
+        Path::new(&name)
+            .file_stem()
+            .unwrap_or_default()
+            .to_os_string()
+    }
+}
+
+/// Match files by name only
+#[derive(Default)]
+pub struct NameComparer {
+    pub key: NameKey,
+}
+
+impl Comparer<FileData> for NameComparer {
+    fn equals(&self, a: &FileData, b: &FileData) -> bool {
+        self.key.apply(a.name()) == self.key.apply(b.name())
+    }
+
+    fn hash(&self, value: &FileData) -> usize {
+        get_hash(&self.key.apply(value.name()))
+    }
+}
+
+/// Match files by name and size
+#[derive(Default)]
+pub struct NameSizeComparer {
+    pub key: NameKey,
+}
+
+impl Comparer<FileData> for NameSizeComparer {
+    fn equals(&self, a: &FileData, b: &FileData) -> bool {
+        a.size == b.size && self.key.apply(a.name()) == self.key.apply(b.name())
+    }
+
+    fn hash(&self, value: &FileData) -> usize {
+        get_hash(&(self.key.apply(value.name()), value.size))
+    }
+}
+
+/// Match files by name without the extension, so converted media such as `song.flac` and `song.mp3` match
+#[derive(Default)]
+pub struct StemComparer {
+    pub key: NameKey,
+}
+
+impl Comparer<FileData> for StemComparer {
+    fn equals(&self, a: &FileData, b: &FileData) -> bool {
+        self.key.apply_stem(a.name()) == self.key.apply_stem(b.name())
+    }
+
+    fn hash(&self, value: &FileData) -> usize {
+        get_hash(&self.key.apply_stem(value.name()))
+    }
+}
+
+/// Match files by the hash of their contents, whichever comparison computed it. Files without one never match
+pub struct ContentComparer;
+
+impl Comparer<FileData> for ContentComparer {
+    fn equals(&self, a: &FileData, b: &FileData) -> bool {
+        a.contents.is_some() && a.contents == b.contents
+    }
+
+    fn hash(&self, value: &FileData) -> usize {
+        get_hash(&value.contents)
+    }
+}
+
+/// The built-in comparer for a comparison option. `key` applies to comparisons by name
+#[must_use]
+pub fn builtin_comparer(option: FileDataCompareOption, key: NameKey) -> FileComparer {
+    match option {
+        FileDataCompareOption::Name => Arc::new(NameComparer { key }),
+        FileDataCompareOption::NameSize => Arc::new(NameSizeComparer { key }),
+        FileDataCompareOption::Stem => Arc::new(StemComparer { key }),
+        FileDataCompareOption::Hash
+        | FileDataCompareOption::QuickHash
+        | FileDataCompareOption::GitBlob => Arc::new(ContentComparer),
+    }
+}
+
+/// Helper to get the hash of a single value (including tuples)
+#[inline]
+#[allow(clippy::cast_possible_truncation)]
+#[must_use]
+pub fn get_hash<T: Hash + ?Sized>(t: &T) -> usize {
+    // get_hash("hello")
+    // get_hash(&("hello", 99))
+    let mut s = DefaultHasher::new();
+    t.hash(&mut s);
+    s.finish() as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transform(text: &str, name: &str) -> String {
+        NameTransform::parse(text).unwrap().apply(name).into_owned()
+    }
+
+    #[test]
+    fn first_match_is_replaced() {
+        assert_eq!(transform("s/a/b/", "banana"), "bbnana");
+    }
+
+    #[test]
+    fn g_flag_replaces_every_match() {
+        assert_eq!(transform("s/a/b/g", "banana"), "bbnbnb");
+    }
+
+    #[test]
+    fn groups_can_be_used_in_the_replacement() {
+        let copy_suffix = r"s/ \(\d+\)(\.[^.]+)$/$1/";
+        assert_eq!(transform(copy_suffix, "IMG_1234 (1).jpg"), "IMG_1234.jpg");
+        assert_eq!(transform(copy_suffix, "IMG_1234.jpg"), "IMG_1234.jpg");
+    }
+
+    #[test]
+    fn any_delimiter_and_escaped_delimiters() {
+        assert_eq!(transform("s|/|_|g", "a/b/c"), "a_b_c");
+        assert_eq!(transform(r"s/\//-/", "a/b"), "a-b");
+        assert_eq!(transform("s#x#y#", "xx"), "yx");
+    }
+
+    #[test]
+    fn empty_replacement_removes_the_match() {
+        assert_eq!(transform(r"s/\.bak$//", "notes.txt.bak"), "notes.txt");
+    }
+
+    #[test]
+    fn malformed_transforms_are_rejected() {
+        for text in ["", "s", "x/a/b/", "s/a/b", "s/a/b/c/", "s/a/b/i", "s/(/x/"] {
+            assert!(NameTransform::parse(text).is_err(), "{text}");
+        }
+    }
+
+    #[test]
+    fn error_messages_explain_the_problem() {
+        let message = |text| NameTransform::parse(text).unwrap_err().to_string();
+        assert!(message("s/a/b").contains("should look like s/regex/replacement/"));
+        assert!(message("s/a/b/i").contains("flags can only be g"));
+        assert!(message("s/(/x/").contains("Invalid name transform regex"));
+    }
+
+    #[test]
+    fn name_key_applies_transforms_in_order_before_folding() {
+        let key = NameKey {
+            transforms: vec![
+                NameTransform::parse("s/-final//").unwrap(),
+                NameTransform::parse("s/_/ /g").unwrap(),
+            ],
+            form: NameForm {
+                case: NameCase::Insensitive,
+                normalize: NameNormalize::None,
+            },
+        };
+        assert_eq!(
+            &*key.apply(OsStr::new("My_Report-final.PDF")),
+            "my report.pdf"
+        );
+        assert_eq!(
+            key.apply_stem(OsStr::new("My_Report-final.PDF")),
+            "my report"
+        );
+    }
+
+    fn form(case: NameCase, normalize: NameNormalize) -> NameForm {
+        NameForm { case, normalize }
+    }
+
+    // "café" with the accent as one code point, and as an e followed by a combining accent
+    const COMPOSED: &str = "caf\u{e9}";
+    const DECOMPOSED: &str = "cafe\u{301}";
+
+    #[test]
+    fn names_are_untouched_by_default() {
+        let name = OsStr::new(DECOMPOSED);
+        assert!(matches!(NameForm::default().apply(name), Cow::Borrowed(_)));
+        assert_ne!(
+            NameForm::default().apply(OsStr::new(COMPOSED)),
+            NameForm::default().apply(OsStr::new(DECOMPOSED))
+        );
+    }
+
+    #[test]
+    fn normal_forms_match_composed_and_decomposed_names() {
+        for (normalize, expected) in [
+            (NameNormalize::Nfc, COMPOSED),
+            (NameNormalize::Nfd, DECOMPOSED),
+        ] {
+            let form = form(NameCase::Sensitive, normalize);
+            for name in [COMPOSED, DECOMPOSED] {
+                assert_eq!(
+                    form.apply(OsStr::new(name)),
+                    OsStr::new(expected),
+                    "{normalize:?}"
+                );
+            }
+        }
+
+        // compatibility forms fold ligatures and the like too, which NFC leaves alone
+        let ligature = OsStr::new("\u{fb01}le");
+        let compatible = form(NameCase::Sensitive, NameNormalize::Nfkc);
+        let canonical = form(NameCase::Sensitive, NameNormalize::Nfc);
+        assert_eq!(compatible.apply(ligature), OsStr::new("file"));
+        assert_eq!(canonical.apply(ligature), ligature);
+    }
+
+    #[test]
+    fn case_folding_covers_unicode_letters() {
+        let form = form(NameCase::Insensitive, NameNormalize::Nfc);
+        assert_eq!(
+            form.apply(OsStr::new("CAF\u{c9}.TXT")),
+            OsStr::new("caf\u{e9}.txt")
+        );
+        assert_eq!(
+            form.apply(OsStr::new("CAFE\u{301}.TXT")),
+            form.apply(OsStr::new("caf\u{e9}.txt"))
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn names_that_are_not_unicode_only_fold_ascii() {
+        use std::os::unix::ffi::OsStrExt;
+
+        // Latin-1 "CAFÉ", which isn't valid UTF-8
+        let name = OsStr::from_bytes(b"CAF\xc9");
+        let folded = form(NameCase::Insensitive, NameNormalize::Nfc).apply(name);
+        assert_eq!(folded.as_bytes(), b"caf\xc9");
+
+        let sensitive = form(NameCase::Sensitive, NameNormalize::Nfd).apply(name);
+        assert_eq!(sensitive.as_bytes(), name.as_bytes());
+    }
+
+    #[test]
+    fn paths_are_folded_a_component_at_a_time() {
+        let form = form(NameCase::Insensitive, NameNormalize::Nfc);
+        let path = Path::new("Photos").join(DECOMPOSED).join("IMG.JPG");
+        let expected = Path::new("photos").join(COMPOSED).join("img.jpg");
+        assert_eq!(form.apply_path(&path), expected);
+        assert_eq!(NameForm::default().apply_path(&path), path);
+    }
+}
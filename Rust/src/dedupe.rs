@@ -0,0 +1,313 @@
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+
+use crate::ScanOutput;
+use crate::cancel::CancelToken;
+use crate::filedata::{DedupeMode, HashKey};
+use crate::sync::{copy_times, log_action};
+use crate::utils::Config;
+
+const COMPARE_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Replace duplicate files, within either tree or across both, with links to one kept copy.
+/// The first file in A with given contents is kept. Every pair is compared byte for byte before anything changes
+/// # Errors
+/// Will return an error if any replacements failed
+pub fn run(config: &Config, mode: DedupeMode, scan: &ScanOutput) -> anyhow::Result<()> {
+    let mut failures = 0;
+    let mut saved = 0;
+
+    for group in duplicate_groups(scan) {
+        let Some((keeper, duplicates)) = group.split_first() else {
+            continue;
+        };
+
+        for duplicate in duplicates {
+            if config.cancel.is_cancelled() {
+                log_action("CANCELLED", "remaining duplicates skipped");
+                return finish(failures, saved);
+            }
+
+            let detail = format!("{} -> {}", duplicate.display(), keeper.display());
+            match dedupe_pair(mode, keeper, duplicate, config.dry_run, &config.cancel) {
+                Ok(Outcome::Replaced(size)) => {
+                    saved += size;
+                    let verb = match (mode, config.dry_run) {
+                        (DedupeMode::Hardlink, false) => "LINK",
+                        (DedupeMode::Hardlink, true) => "DRY-RUN LINK",
+                        (DedupeMode::Reflink, false) => "REFLINK",
+                        (DedupeMode::Reflink, true) => "DRY-RUN REFLINK",
+                    };
+                    log_action(verb, &detail);
+                }
+                Ok(Outcome::Skipped(reason)) => log_action("SKIP", &format!("{detail} ({reason})")),
+                Err(e) => {
+                    log_action("FAILED", &format!("{detail}: {e}"));
+                    failures += 1;
+                }
+            }
+        }
+    }
+
+    finish(failures, saved)
+}
+
+/// Report the space reclaimed, and fail if anything went wrong
+fn finish(failures: usize, saved: u64) -> anyhow::Result<()> {
+    eprintln!("Deduplication reclaimed {saved} bytes");
+    if failures > 0 {
+        return Err(anyhow::anyhow!("{failures} dedupe action(s) failed"));
+    }
+    Ok(())
+}
+
+/// What happened to one duplicate
+enum Outcome {
+    Replaced(u64),
+    Skipped(&'static str),
+}
+
+/// Group every scanned file by content hash, keeping only groups with more than one file.
+/// Each group is ordered A before B, then by path, so the kept copy is predictable
+fn duplicate_groups(scan: &ScanOutput) -> Vec<Vec<&Path>> {
+    let mut groups: HashMap<&HashKey, Vec<(usize, &Path)>> = HashMap::new();
+
+    for (side, folder) in [&scan.scan1, &scan.scan2].into_iter().enumerate() {
+        for file in folder.iter().chain(&folder.duplicates) {
+            if let Some(key) = &file.contents {
+                groups.entry(key).or_default().push((side, &file.path.0));
+            }
+        }
+    }
+
+    let mut groups: Vec<Vec<&Path>> = groups
+        .into_values()
+        .filter(|g| g.len() > 1)
+        .map(|mut g| {
+            g.sort_unstable();
+            g.into_iter().map(|(_, path)| path).collect()
+        })
+        .collect();
+    groups.sort_unstable();
+    groups
+}
+
+/// Replace `duplicate` with a link to `keeper`, after proving they are identical
+fn dedupe_pair(
+    mode: DedupeMode,
+    keeper: &Path,
+    duplicate: &Path,
+    dry_run: bool,
+    cancel: &CancelToken,
+) -> anyhow::Result<Outcome> {
+    let keeper_meta = fs::metadata(keeper)?;
+    let duplicate_meta = fs::metadata(duplicate)?;
+
+    if duplicate_meta.len() == 0 {
+        return Ok(Outcome::Skipped("empty file"));
+    }
+    if same_file::is_same_file(keeper, duplicate)? {
+        return Ok(Outcome::Skipped("already linked"));
+    }
+    if !same_device(&keeper_meta, &duplicate_meta) {
+        return Ok(Outcome::Skipped("different filesystems"));
+    }
+    // a hard link shares one set of metadata, so it would silently change the duplicate's permissions, owner
+    // and times
+    if mode == DedupeMode::Hardlink
+        && let Some(difference) = shared_metadata_difference(&keeper_meta, &duplicate_meta)
+    {
+        return Ok(Outcome::Skipped(difference));
+    }
+    if !files_identical(keeper, duplicate, cancel)? {
+        return Ok(Outcome::Skipped("contents differ"));
+    }
+    if dry_run {
+        return Ok(Outcome::Replaced(duplicate_meta.len()));
+    }
+
+    // link alongside, then rename over the duplicate so it is never missing
+    let temp = temp_path(duplicate);
+    let linked = match mode {
+        DedupeMode::Hardlink => fs::hard_link(keeper, &temp).map_err(anyhow::Error::from),
+        DedupeMode::Reflink => reflink_copy::reflink(keeper, &temp)
+            .map_err(anyhow::Error::from)
+            .and_then(|()| restore_metadata(duplicate, &duplicate_meta, &temp)),
+    };
+
+    let result = linked.and_then(|()| Ok(fs::rename(&temp, duplicate)?));
+    if let Err(e) = result {
+        let _ = fs::remove_file(&temp);
+        return Err(e);
+    }
+
+    Ok(Outcome::Replaced(duplicate_meta.len()))
+}
+
+/// Links can't cross filesystems, so pairs on different devices are skipped rather than failing
+#[cfg(unix)]
+fn same_device(a: &fs::Metadata, b: &fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    a.dev() == b.dev()
+}
+
+/// Only Unix exposes the device, elsewhere a link across volumes fails and is reported
+#[cfg(not(unix))]
+fn same_device(_: &fs::Metadata, _: &fs::Metadata) -> bool {
+    true
+}
+
+/// The first difference in the metadata a hard link would share, if any
+fn shared_metadata_difference(a: &fs::Metadata, b: &fs::Metadata) -> Option<&'static str> {
+    if a.permissions() != b.permissions() {
+        return Some("permissions differ");
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        if a.uid() != b.uid() {
+            return Some("owners differ");
+        }
+        if a.gid() != b.gid() {
+            return Some("groups differ");
+        }
+    }
+    if a.modified().ok() != b.modified().ok() {
+        return Some("modification times differ");
+    }
+    None
+}
+
+/// Give a reflinked clone the permissions, ownership and times of the file it replaces
+fn restore_metadata(original: &Path, metadata: &fs::Metadata, clone: &Path) -> anyhow::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        std::os::unix::fs::chown(clone, Some(metadata.uid()), Some(metadata.gid()))?;
+    }
+    fs::set_permissions(clone, metadata.permissions())?;
+    copy_times(original, clone)
+}
+
+/// Hidden temporary name next to a file
+fn temp_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(".folder_compare.dedupe");
+    path.with_file_name(name)
+}
+
+/// Compare two files byte for byte
+/// # Errors
+/// Will return an error if either file can't be read, or the comparison was cancelled
+pub fn files_identical(a: &Path, b: &Path, cancel: &CancelToken) -> anyhow::Result<bool> {
+    let mut reader_a = BufReader::new(File::open(a)?);
+    let mut reader_b = BufReader::new(File::open(b)?);
+    let mut buffer_a = vec![0u8; COMPARE_BUFFER_SIZE];
+    let mut buffer_b = vec![0u8; COMPARE_BUFFER_SIZE];
+
+    loop {
+        cancel.check()?;
+        let n = read_full(&mut reader_a, &mut buffer_a)?;
+        let m = read_full(&mut reader_b, &mut buffer_b)?;
+        if n != m || buffer_a[..n] != buffer_b[..m] {
+            return Ok(false);
+        }
+        if n == 0 {
+            return Ok(true);
+        }
+    }
+}
+
+/// Fill as much of the buffer as possible, so both files are compared in step
+fn read_full(reader: &mut impl Read, buffer: &mut [u8]) -> std::io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        let n = reader.read(&mut buffer[filled..])?;
+        if n == 0 {
+            break;
+        }
+        filled += n;
+    }
+    Ok(filled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use filetime::FileTime;
+
+    /// Two identical files in a fresh folder, removed when dropped
+    struct TempPair(PathBuf);
+
+    impl TempPair {
+        fn new(name: &str) -> Self {
+            let root = std::env::temp_dir().join(format!(
+                "folder_compare_dedupe_{name}_{}",
+                std::process::id()
+            ));
+            let _ = fs::remove_dir_all(&root);
+            fs::create_dir_all(&root).unwrap();
+            for file in ["x", "y"] {
+                fs::write(root.join(file), "same contents").unwrap();
+            }
+            let time = FileTime::from_unix_time(1_700_000_000, 0);
+            for file in ["x", "y"] {
+                filetime::set_file_times(root.join(file), time, time).unwrap();
+            }
+            TempPair(root)
+        }
+    }
+
+    impl Drop for TempPair {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn hardlink_skips_a_different_mtime() {
+        let pair = TempPair::new("mtime");
+        let (x, y) = (pair.0.join("x"), pair.0.join("y"));
+        let old = FileTime::from_unix_time(978_307_200, 0); // 2001
+        filetime::set_file_mtime(&y, old).unwrap();
+
+        let outcome =
+            dedupe_pair(DedupeMode::Hardlink, &x, &y, false, &CancelToken::new()).unwrap();
+        assert!(matches!(
+            outcome,
+            Outcome::Skipped("modification times differ")
+        ));
+        assert!(!same_file::is_same_file(&x, &y).unwrap());
+        let mtime = FileTime::from_last_modification_time(&fs::metadata(&y).unwrap());
+        assert_eq!(mtime, old);
+    }
+
+    #[test]
+    fn hardlink_skips_different_permissions() {
+        let pair = TempPair::new("mode");
+        let (x, y) = (pair.0.join("x"), pair.0.join("y"));
+        let mut permissions = fs::metadata(&y).unwrap().permissions();
+        permissions.set_readonly(true);
+        fs::set_permissions(&y, permissions).unwrap();
+
+        let outcome =
+            dedupe_pair(DedupeMode::Hardlink, &x, &y, false, &CancelToken::new()).unwrap();
+        assert!(matches!(outcome, Outcome::Skipped("permissions differ")));
+        assert!(!same_file::is_same_file(&x, &y).unwrap());
+    }
+
+    #[test]
+    fn hardlink_links_matching_files() {
+        let pair = TempPair::new("link");
+        let (x, y) = (pair.0.join("x"), pair.0.join("y"));
+
+        let outcome =
+            dedupe_pair(DedupeMode::Hardlink, &x, &y, false, &CancelToken::new()).unwrap();
+        assert!(matches!(outcome, Outcome::Replaced(13)));
+        assert!(same_file::is_same_file(&x, &y).unwrap());
+    }
+}
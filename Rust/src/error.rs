@@ -0,0 +1,83 @@
+use std::fmt::{Display, Formatter};
+
+use crate::cancel;
+
+/// Errors returned by the library API. Internally errors carry context as `anyhow::Error`, and are sorted into
+/// these variants where they leave the library
+#[derive(Debug)]
+pub enum Error {
+    /// The scan was stopped through its `CancelToken`, or by a signal
+    Cancelled,
+    /// A file, folder or list could not be read
+    Io(std::io::Error),
+    /// Anything else, such as an unreadable archive or a failed git command, with its context
+    Other(anyhow::Error),
+}
+
+// both are passed through to the wrapped error, so its message is shown once, then whatever caused it
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Cancelled => write!(f, "Cancelled"),
+            Error::Io(e) => write!(f, "{e}"),
+            Error::Other(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Cancelled => None,
+            Error::Io(e) => e.source(),
+            Error::Other(e) => e.source(),
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<anyhow::Error> for Error {
+    fn from(e: anyhow::Error) -> Self {
+        if cancel::is_cancellation(&e) {
+            return Error::Cancelled;
+        }
+        // a bare I/O error is passed on as one, but context would be lost by unwrapping anything else
+        if e.chain().count() == 1 {
+            return match e.downcast::<std::io::Error>() {
+                Ok(io) => Error::Io(io),
+                Err(e) => Error::Other(e),
+            };
+        }
+        Error::Other(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn each_message_is_shown_once() {
+        let inner: anyhow::Result<()> = Err(anyhow::anyhow!("invalid Zip archive"));
+        let error = Error::from(inner.context("Cannot read archive 'bad.zip'").unwrap_err());
+        let shown = format!("{:?}", anyhow::Error::from(error));
+        assert_eq!(shown.matches("invalid Zip archive").count(), 1, "{shown}");
+        assert_eq!(shown.matches("Cannot read archive").count(), 1, "{shown}");
+    }
+
+    #[test]
+    fn io_errors_are_not_repeated() {
+        let error = Error::from(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            "no such file",
+        ));
+        let shown = format!("{:?}", anyhow::Error::from(error));
+        assert_eq!(shown.matches("no such file").count(), 1, "{shown}");
+    }
+}
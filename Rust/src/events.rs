@@ -0,0 +1,85 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::mpsc::Sender;
+
+use crate::filedata::{FilePath, HashKey};
+
+/// Something that happened during a scan, for embedding applications that show their own progress and results.
+/// `side` is the index of the folder, 0 for A and 1 for B
+#[derive(Debug, Clone)]
+pub enum Event {
+    /// A file was added to a side's scan
+    FileScanned {
+        side: usize,
+        path: FilePath,
+        size: u64,
+    },
+    /// A file's contents were hashed, for comparisons that read them. Sent just before `FileScanned`
+    FileHashed {
+        side: usize,
+        path: FilePath,
+        hash: HashKey,
+    },
+    /// A file or folder couldn't be read while walking, and was skipped
+    WalkError {
+        side: usize,
+        path: Option<PathBuf>,
+        message: String,
+    },
+    /// Something was worked around rather than compared as usual, eg an unreadable archive compared as a file,
+    /// or a file whose metadata couldn't be read. `side` is None when it concerns both sides. `message` is a
+    /// complete sentence naming the path, ready to show
+    Warning {
+        side: Option<usize>,
+        path: PathBuf,
+        message: String,
+    },
+    /// A file on one side has no match on the other
+    DifferenceFound { side: usize, path: FilePath },
+    /// The comparison is complete. `incomplete` is set if it was cancelled
+    Finished {
+        differences: usize,
+        incomplete: bool,
+    },
+}
+
+/// The kinds of `Event`, so a sink can say which it wants
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    FileScanned,
+    FileHashed,
+    WalkError,
+    Warning,
+    DifferenceFound,
+    Finished,
+}
+
+/// Receives events as a scan runs. Events are sent from the scanning threads, so the sink must be thread safe
+pub trait EventSink: Send + Sync {
+    fn event(&self, event: Event);
+
+    /// Should events of this kind be sent? Those that aren't are never built, so a sink that only wants
+    /// problems doesn't slow the scan down with an event for every file. Default is every kind
+    fn wants(&self, kind: EventKind) -> bool {
+        let _ = kind;
+        true
+    }
+}
+
+/// An event sink shared by the scanning threads
+pub type SharedEventSink = Arc<dyn EventSink>;
+
+/// Events can be sent down a channel, and received on another thread
+impl EventSink for Sender<Event> {
+    fn event(&self, event: Event) {
+        // the receiver going away just means nobody is listening any more
+        let _ = self.send(event);
+    }
+}
+
+/// Or handled by a callback
+impl<F: Fn(Event) + Send + Sync> EventSink for F {
+    fn event(&self, event: Event) {
+        self(event);
+    }
+}
@@ -1,8 +1,11 @@
 use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use strum::EnumString;
 
 /// convert comparison string into an instance of `FileDataCompareOption`
+/// # Errors
+/// Will return an error if the string is not a recognised `FileDataCompareOption`
 pub fn parse_comparer(
     comparer_str: Option<&String>,
 ) -> Result<FileDataCompareOption, strum::ParseError> {
@@ -14,18 +17,17 @@ pub fn parse_comparer(
 
 // =================================================================================================
 
-// A struct to hold the hash value, without the overhead of a String. 32 bytes long
+// A struct to hold the hash value, without the overhead of a String. Length depends on the hash algorithm
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
-pub struct Sha2Hash {
-    hash: [u8; 32],
+pub struct HashKey {
+    hash: Box<[u8]>,
 }
 
-impl Sha2Hash {
-    /// Create a new `Sha2Hash` from a u8 slice
+impl HashKey {
+    /// Create a new `HashKey` from a u8 slice, of any length
+    #[must_use]
     pub fn new(slice: &[u8]) -> Self {
-        let mut hash = [0u8; 32];
-        hash.copy_from_slice(slice); // this automatically checks the length of slice is correct
-        Sha2Hash { hash }
+        HashKey { hash: slice.into() }
     }
 
     // /// Get the hash as a immutable slice
@@ -34,9 +36,9 @@ impl Sha2Hash {
     // }
 }
 
-impl Display for Sha2Hash {
+impl Display for HashKey {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        for byte in self.hash {
+        for byte in &self.hash {
             write!(f, "{byte:02x}")?;
         }
         Ok(())
@@ -46,39 +48,255 @@ impl Display for Sha2Hash {
 // =================================================================================================
 
 /// Type of comparison to use
-#[derive(Debug, Copy, Clone, PartialEq, Eq, EnumString)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, EnumString)]
 #[strum(ascii_case_insensitive)]
 pub enum FileDataCompareOption {
+    #[default]
     #[strum(serialize = "name")]
     Name,
     #[strum(serialize = "namesize")]
     NameSize,
+    #[strum(serialize = "stem")]
+    Stem, // name without its extension, so song.flac matches song.mp3
     #[strum(serialize = "hash")]
     Hash,
+    #[strum(serialize = "quickhash")]
+    QuickHash,
+    #[strum(serialize = "gitblob")]
+    GitBlob, // git's SHA-1 blob id, so files match a git tree without reading the repo's contents
+}
+
+/// Hash algorithm used for keys and file contents
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, EnumString)]
+#[strum(ascii_case_insensitive)]
+pub enum HashAlgorithm {
+    #[strum(serialize = "blake3")]
+    Blake3,
+    #[default]
+    #[strum(serialize = "sha256", serialize = "sha2")]
+    Sha256,
+    #[strum(serialize = "sha512")]
+    Sha512,
+    #[strum(serialize = "sha1")]
+    Sha1,
+    #[strum(serialize = "md5")]
+    Md5,
+    #[strum(serialize = "xxh3")]
+    Xxh3,
+}
+
+/// convert algorithm string into an instance of `HashAlgorithm`
+/// # Errors
+/// Will return an error if the string is not a recognised `HashAlgorithm`
+pub fn parse_algorithm(algorithm_str: Option<&String>) -> Result<HashAlgorithm, strum::ParseError> {
+    match algorithm_str {
+        Some(s) if !s.is_empty() => HashAlgorithm::from_str(s), // a non-empty string
+        _ => Ok(HashAlgorithm::Sha256),                         // otherwise, use the default
+    }
+}
+
+/// How file contents are read when hashing
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, EnumString)]
+#[strum(ascii_case_insensitive)]
+pub enum IoStrategy {
+    /// Buffered for small files, Large for bigger ones
+    #[default]
+    #[strum(serialize = "auto")]
+    Auto,
+    /// Small buffer via `BufReader`. Best for many small files
+    #[strum(serialize = "buffered")]
+    Buffered,
+    /// Large page-aligned buffer, with sequential read-ahead hints on Linux. Best for big files
+    #[strum(serialize = "large")]
+    Large,
+}
+
+/// convert I/O strategy string into an instance of `IoStrategy`
+/// # Errors
+/// Will return an error if the string is not a recognised `IoStrategy`
+pub fn parse_io_strategy(io_str: Option<&String>) -> Result<IoStrategy, strum::ParseError> {
+    match io_str {
+        Some(s) if !s.is_empty() => IoStrategy::from_str(s), // a non-empty string
+        _ => Ok(IoStrategy::Auto),                           // otherwise, use the default
+    }
+}
+
+/// Whether letter case matters when comparing names
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, EnumString)]
+#[strum(ascii_case_insensitive)]
+pub enum NameCase {
+    #[default]
+    #[strum(serialize = "sensitive")]
+    Sensitive,
+    /// `Photo.JPG` matches `photo.jpg`, as on Windows and macOS
+    #[strum(serialize = "insensitive")]
+    Insensitive,
+}
+
+/// convert name case string into an instance of `NameCase`
+/// # Errors
+/// Will return an error if the string is not a recognised `NameCase`
+pub fn parse_name_case(case_str: Option<&String>) -> Result<NameCase, strum::ParseError> {
+    match case_str {
+        Some(s) if !s.is_empty() => NameCase::from_str(s), // a non-empty string
+        _ => Ok(NameCase::Sensitive),                      // otherwise, use the default
+    }
+}
+
+/// Unicode normalisation applied to names before comparing them, so the same name in different forms matches
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, EnumString)]
+#[strum(ascii_case_insensitive)]
+pub enum NameNormalize {
+    /// Compare the bytes as they are
+    #[default]
+    #[strum(serialize = "none")]
+    None,
+    /// Composed, as most Linux and Windows software writes names
+    #[strum(serialize = "nfc")]
+    Nfc,
+    /// Decomposed, as macOS HFS+ stores names
+    #[strum(serialize = "nfd")]
+    Nfd,
+    /// Composed, with compatibility characters such as ligatures and full-width forms folded too
+    #[strum(serialize = "nfkc")]
+    Nfkc,
+}
+
+/// convert name normalisation string into an instance of `NameNormalize`
+/// # Errors
+/// Will return an error if the string is not a recognised `NameNormalize`
+pub fn parse_name_normalize(
+    normalize_str: Option<&String>,
+) -> Result<NameNormalize, strum::ParseError> {
+    match normalize_str {
+        Some(s) if !s.is_empty() => NameNormalize::from_str(s), // a non-empty string
+        _ => Ok(NameNormalize::None),                           // otherwise, use the default
+    }
+}
+
+/// When output is coloured, from `--color`
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, EnumString)]
+#[strum(ascii_case_insensitive)]
+pub enum ColorMode {
+    /// When stdout is a terminal and `NO_COLOR` isn't set
+    #[default]
+    #[strum(serialize = "auto")]
+    Auto,
+    #[strum(serialize = "always")]
+    Always,
+    #[strum(serialize = "never")]
+    Never,
+}
+
+/// Which paths `--check-names` compares with each other
+#[derive(Debug, Copy, Clone, PartialEq, Eq, EnumString)]
+#[strum(ascii_case_insensitive)]
+pub enum NameCheckScope {
+    /// Each tree on its own
+    #[strum(serialize = "tree")]
+    Tree,
+    /// All the trees together, as if copied into one folder
+    #[strum(serialize = "union")]
+    Union,
+}
+
+/// Metadata that `--check-meta` compares between files at the same relative path
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, EnumString)]
+#[strum(ascii_case_insensitive)]
+pub enum MetaField {
+    /// Permission bits, including setuid, setgid and sticky
+    #[strum(serialize = "mode")]
+    Mode,
+    #[strum(serialize = "uid")]
+    Uid,
+    #[strum(serialize = "gid")]
+    Gid,
+    /// Modification time, within `--mtime-tolerance`
+    #[strum(serialize = "mtime")]
+    Mtime,
+    /// Extended attributes, other than the ones holding ACLs
+    #[strum(serialize = "xattr")]
+    Xattr,
+    /// POSIX access control list
+    #[strum(serialize = "acl")]
+    Acl,
+}
+
+/// convert a comma separated list, eg `mode,mtime`, into `MetaField`s, sorted and without repeats
+/// # Errors
+/// Will return an error if any item is not a recognised `MetaField`
+pub fn parse_meta_fields(fields_str: &str) -> Result<Vec<MetaField>, strum::ParseError> {
+    let mut fields = fields_str
+        .split(',')
+        .map(|field| MetaField::from_str(field.trim()))
+        .collect::<Result<Vec<_>, _>>()?;
+    fields.sort_unstable();
+    fields.dedup();
+    Ok(fields)
+}
+
+/// How identical files are merged by `--dedupe`
+#[derive(Debug, Copy, Clone, PartialEq, Eq, EnumString)]
+#[strum(ascii_case_insensitive)]
+pub enum DedupeMode {
+    /// Hard link to a single inode. The files then share permissions and times too
+    #[strum(serialize = "hardlink")]
+    Hardlink,
+    /// Copy-on-write clone (FICLONE on Linux). Separate files sharing storage, each with its own metadata
+    #[strum(serialize = "reflink")]
+    Reflink,
 }
 
-/// Represents a file path
+/// What a sync script should make folder B look like, relative to folder A
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, EnumString)]
+#[strum(ascii_case_insensitive)]
+pub enum SyncPolicy {
+    /// Copy files only in A into B
+    #[default]
+    #[strum(serialize = "copy-missing")]
+    CopyMissing,
+    /// Make B match A by relative path: copy, replace changed files and delete extras
+    #[strum(serialize = "mirror")]
+    Mirror,
+    /// Delete files only in B
+    #[strum(serialize = "delete-extra")]
+    DeleteExtra,
+}
+
+/// convert policy string into an instance of `SyncPolicy`
+/// # Errors
+/// Will return an error if the string is not a recognised `SyncPolicy`
+pub fn parse_policy(policy_str: Option<&String>) -> Result<SyncPolicy, strum::ParseError> {
+    match policy_str {
+        Some(s) if !s.is_empty() => SyncPolicy::from_str(s), // a non-empty string
+        _ => Ok(SyncPolicy::CopyMissing),                    // otherwise, use the default
+    }
+}
+
+/// Script languages that `--emit-script` can write
+#[derive(Debug, Copy, Clone, PartialEq, Eq, EnumString)]
+#[strum(ascii_case_insensitive)]
+pub enum ScriptFormat {
+    /// POSIX shell
+    #[strum(serialize = "sh")]
+    Sh,
+}
+
+/// Represents a file path. A `PathBuf` rather than a `String`, so names that aren't valid UTF-8 survive
 #[derive(Debug, Clone)]
-pub struct FilePath(pub String);
+pub struct FilePath(pub PathBuf);
 
 impl Display for FilePath {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.0)
+        write!(f, "{}", self.0.display())
     }
 }
 
-// Converting a String and &str into a FilePath
-
-// impl From<String> for FilePath {
-//     // this moves the specified string into the FilePath struct
-//     fn from(s: String) -> Self {
-//         FilePath(s)
-//     }
-// }
+// Converting a Path into a FilePath
 
-impl From<&str> for FilePath {
-    fn from(s: &str) -> Self {
-        // this clones the specified string slice
-        FilePath(s.to_string())
+impl From<&Path> for FilePath {
+    fn from(p: &Path) -> Self {
+        // this clones the specified path
+        FilePath(p.to_path_buf())
     }
 }
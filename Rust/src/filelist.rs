@@ -0,0 +1,192 @@
+use std::fs;
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+use walkdir::{DirEntry, WalkDir};
+
+use crate::events::{Event, EventKind};
+use crate::scan::walk_error_message;
+use crate::utils::{Config, path_from_bytes};
+
+/// Name given for a list to read it from stdin
+pub const STDIN_LIST: &str = "-";
+
+/// Read a list of files to compare instead of walking a folder, eg the output of `find` or `git ls-files`.
+/// Paths are separated by NUL if there are any, otherwise by newlines. Relative paths are relative to `root`.
+/// Entries that aren't readable files are skipped, and sent as `WalkError` events for `side`
+/// # Errors
+/// Will return an error if the list cannot be read
+pub fn read_list(
+    config: &Config,
+    side: usize,
+    list: &Path,
+    root: &Path,
+) -> anyhow::Result<Vec<DirEntry>> {
+    let mut contents = Vec::new();
+    if list.as_os_str() == STDIN_LIST {
+        std::io::stdin().lock().read_to_end(&mut contents)?;
+    } else {
+        contents = fs::read(list)
+            .map_err(|e| anyhow::anyhow!("Cannot read list '{}': {e}", list.display()))?;
+    }
+
+    let separator = if contents.contains(&0) { 0 } else { b'\n' };
+
+    let mut entries = Vec::new();
+    for line in contents.split(|&b| b == separator) {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        if line.is_empty() {
+            continue;
+        }
+
+        // a walk of just the path gives the same entry a folder walk would. `./` from `find .` is dropped
+        let path: PathBuf = root
+            .join(path_from_bytes(line))
+            .components()
+            .filter(|component| component != &Component::CurDir)
+            .collect();
+        let skipped = match WalkDir::new(&path).max_depth(0).into_iter().next() {
+            Some(Ok(entry)) if entry.file_type().is_file() => {
+                entries.push(entry);
+                continue;
+            }
+            Some(Ok(_)) => "listed, but not a file".to_string(),
+            Some(Err(e)) => walk_error_message(&e),
+            None => continue,
+        };
+        config.emit(EventKind::WalkError, || Event::WalkError {
+            side,
+            path: Some(path),
+            message: skipped,
+        });
+    }
+
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::EventSink;
+    use std::sync::{Arc, Mutex};
+
+    /// A fresh folder under the temp folder, removed when dropped
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let root = std::env::temp_dir().join(format!(
+                "folder_compare_filelist_{name}_{}",
+                std::process::id()
+            ));
+            let _ = fs::remove_dir_all(&root);
+            fs::create_dir_all(root.join("tree/sub")).unwrap();
+            fs::write(root.join("tree/a.txt"), "a").unwrap();
+            fs::write(root.join("tree/sub/b c.txt"), "b").unwrap();
+            TempDir(root)
+        }
+
+        /// Write `contents` as a list file and read it relative to `tree`
+        fn read(&self, config: &Config, contents: &[u8]) -> Vec<PathBuf> {
+            let list = self.0.join("list");
+            fs::write(&list, contents).unwrap();
+            read_list(config, 0, &list, &self.0.join("tree"))
+                .unwrap()
+                .iter()
+                .map(|entry| entry.path().strip_prefix(&self.0).unwrap().to_path_buf())
+                .collect()
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// Records every event it is sent
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<Event>>);
+
+    impl EventSink for Recorder {
+        fn event(&self, event: Event) {
+            self.0.lock().unwrap().push(event);
+        }
+    }
+
+    fn expected() -> Vec<PathBuf> {
+        vec![
+            Path::new("tree/a.txt").to_path_buf(),
+            Path::new("tree/sub/b c.txt").to_path_buf(),
+        ]
+    }
+
+    #[test]
+    fn newline_list_with_crlf_and_blank_lines() {
+        let temp = TempDir::new("newlines");
+        let config = Config::default();
+        assert_eq!(
+            temp.read(&config, b"a.txt\r\n\r\n\nsub/b c.txt\r\n"),
+            expected()
+        );
+    }
+
+    #[test]
+    fn nul_list_keeps_newlines_in_names() {
+        let temp = TempDir::new("nul");
+        let config = Config::default();
+        // with a NUL anywhere, newlines and blank entries are not separators
+        assert_eq!(
+            temp.read(&config, b"./a.txt\0\0./sub/b c.txt\0"),
+            expected()
+        );
+
+        let recorder = Arc::new(Recorder::default());
+        let config = Config {
+            events: Some(recorder.clone()),
+            ..Config::default()
+        };
+        assert_eq!(
+            temp.read(&config, b"a.txt\nsub/b c.txt\0"),
+            Vec::<PathBuf>::new()
+        );
+        assert_eq!(recorder.0.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn relative_and_absolute_paths() {
+        let temp = TempDir::new("relative");
+        let absolute = temp.0.join("tree/sub/b c.txt");
+        let mut list = b"./a.txt\n".to_vec();
+        list.extend_from_slice(absolute.to_string_lossy().as_bytes());
+        list.push(b'\n');
+        assert_eq!(temp.read(&Config::default(), &list), expected());
+    }
+
+    #[test]
+    fn folders_and_missing_files_are_walk_errors() {
+        let temp = TempDir::new("skipped");
+        let recorder = Arc::new(Recorder::default());
+        let config = Config {
+            events: Some(recorder.clone()),
+            ..Config::default()
+        };
+        assert_eq!(
+            temp.read(&config, b"sub\nmissing.txt\na.txt\n"),
+            [Path::new("tree/a.txt")]
+        );
+
+        let events = recorder.0.lock().unwrap();
+        let skipped: Vec<_> = events
+            .iter()
+            .filter_map(|event| match event {
+                Event::WalkError {
+                    side: 0,
+                    path: Some(path),
+                    ..
+                } => path.file_name(),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(skipped, ["sub", "missing.txt"]);
+    }
+}
@@ -0,0 +1,402 @@
+use std::ffi::OsString;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+
+use crate::FolderScan;
+use crate::archive::{member_contents, member_path};
+use crate::cancel;
+use crate::comparer::FileData;
+use crate::filedata::{FileDataCompareOption, FilePath, HashKey};
+use crate::progress::Progress;
+use crate::scan::add_scanned;
+use crate::utils::{Config, path_from_bytes};
+
+const GIT_PREFIX: &str = "git:";
+const SYMLINK_MODE: &str = "120000"; // symlinks are blobs, but aren't files in a folder scan either
+
+/// A git revision compared as if it were a folder, given as `git:<repo>@<rev>`
+pub struct GitSource {
+    pub repo: PathBuf,
+    pub rev: String,
+}
+
+/// A file in the revision's tree, from `git ls-tree`
+#[derive(Debug)]
+struct TreeEntry {
+    oid: String,   // blob id, hex
+    size: u64,     // blob size
+    path: PathBuf, // path within the tree
+}
+
+impl GitSource {
+    /// Parse `git:<repo>@<rev>`, splitting at the last `@`. The revision defaults to HEAD
+    #[must_use]
+    pub fn parse(text: &str) -> Option<Self> {
+        let rest = text.strip_prefix(GIT_PREFIX)?;
+        let (repo, rev) = match rest.rsplit_once('@') {
+            Some((repo, rev)) if !rev.is_empty() => (repo, rev),
+            _ => (rest, "HEAD"),
+        };
+        Some(GitSource {
+            repo: PathBuf::from(repo),
+            rev: rev.to_string(),
+        })
+    }
+
+    /// Recognise a root that was given as a git source
+    pub fn from_root(root: &Path) -> Option<Self> {
+        root.to_str().and_then(Self::parse)
+    }
+
+    /// The source as a root path, with the repository made absolute so it can be compared with other roots
+    /// # Errors
+    /// Will return an error if the repository path doesn't exist
+    pub fn canonical_root(&self) -> anyhow::Result<PathBuf> {
+        let mut root = OsString::from(GIT_PREFIX);
+        root.push(self.repo.canonicalize()?);
+        root.push("@");
+        root.push(&self.rev);
+        Ok(PathBuf::from(root))
+    }
+
+    /// A git command run in the repository
+    fn command(&self) -> Command {
+        let mut command = Command::new("git");
+        command.arg("-C").arg(&self.repo);
+        command
+    }
+}
+
+/// Scan the files of a git revision straight from the object database, with no checkout.
+/// `root` is the source as given, used as the prefix of each path shown
+/// # Errors
+/// Will return an error if git fails, or the revision doesn't exist
+pub fn scan_git(
+    config: &Config,
+    source: &GitSource,
+    root: &Path,
+    progress: &Progress,
+    side: usize,
+) -> anyhow::Result<FolderScan> {
+    let entries = list_tree(source)?;
+    let counters = &progress.sides[side];
+    let mut scan = FolderScan::new(config.file_comparer());
+
+    // git already knows each blob's id, so GitBlob needs nothing read at all
+    let needs_contents =
+        config.reads_contents() && config.comparer != FileDataCompareOption::GitBlob;
+
+    if needs_contents {
+        let result = read_blobs(source, &entries, |entry, reader| {
+            let path = member_path(root, &entry.path);
+            progress.set_current(&path);
+            let contents = member_contents(config, entry.size, reader, counters)?;
+            let file = FileData {
+                path: FilePath(path),
+                size: entry.size,
+                contents,
+            };
+            add_scanned(config, &mut scan, file, side, counters);
+            Ok(())
+        });
+
+        // cancellation keeps what was scanned so far
+        match result {
+            Err(e) if !cancel::is_cancellation(&e) => return Err(e),
+            _ => return Ok(scan),
+        }
+    }
+
+    for entry in &entries {
+        if config.cancel.is_cancelled() {
+            break;
+        }
+
+        let contents = if config.comparer == FileDataCompareOption::GitBlob {
+            Some(oid_key(&entry.oid)?)
+        } else {
+            None
+        };
+        let file = FileData {
+            path: FilePath(member_path(root, &entry.path)),
+            size: entry.size,
+            contents,
+        };
+        add_scanned(config, &mut scan, file, side, counters);
+    }
+
+    Ok(scan)
+}
+
+/// List every regular file in the revision, with `git ls-tree -r -l -z`
+fn list_tree(source: &GitSource) -> anyhow::Result<Vec<TreeEntry>> {
+    // the revision comes from the user, so one starting with `-` mustn't be taken as an option
+    let output = source
+        .command()
+        .args([
+            "ls-tree",
+            "-r",
+            "-l",
+            "-z",
+            "--full-tree",
+            "--end-of-options",
+        ])
+        .arg(&source.rev)
+        .stderr(Stdio::piped())
+        .output()
+        .map_err(|e| anyhow::anyhow!("Cannot run git: {e}"))?;
+
+    if !output.status.success() {
+        return Err(anyhow::anyhow!(
+            "git ls-tree failed for '{}' in '{}': {}",
+            source.rev,
+            source.repo.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    parse_tree(&output.stdout)
+}
+
+/// Parse the NUL separated records of `git ls-tree -r -l -z`, keeping the regular files
+fn parse_tree(output: &[u8]) -> anyhow::Result<Vec<TreeEntry>> {
+    let mut entries = Vec::new();
+    for record in output.split(|&b| b == 0).filter(|r| !r.is_empty()) {
+        // <mode> SP <type> SP <oid> SP+ <size> TAB <path>
+        let tab = record
+            .iter()
+            .position(|&b| b == b'\t')
+            .ok_or_else(|| anyhow::anyhow!("Unexpected git ls-tree output"))?;
+        let info = String::from_utf8_lossy(&record[..tab]);
+        let fields: Vec<&str> = info.split_whitespace().collect();
+        let [mode, kind, oid, size] = fields[..] else {
+            return Err(anyhow::anyhow!("Unexpected git ls-tree output: {info}"));
+        };
+
+        if kind != "blob" || mode == SYMLINK_MODE {
+            continue;
+        }
+
+        entries.push(TreeEntry {
+            oid: oid.to_string(),
+            size: size.parse()?,
+            path: path_from_bytes(&record[tab + 1..]),
+        });
+    }
+
+    Ok(entries)
+}
+
+/// Stream each entry's contents from one `git cat-file --batch` process, in order
+fn read_blobs(
+    source: &GitSource,
+    entries: &[TreeEntry],
+    mut visit: impl FnMut(&TreeEntry, &mut dyn Read) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let mut child = source
+        .command()
+        .args(["cat-file", "--batch"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| anyhow::anyhow!("Cannot run git: {e}"))?;
+
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let stdout = child.stdout.take().expect("stdout is piped");
+
+    // requests are written on another thread, so neither pipe can fill up and deadlock
+    let result = std::thread::scope(|scope| {
+        scope.spawn(move || {
+            for entry in entries {
+                if writeln!(stdin, "{}", entry.oid).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut reader = BufReader::new(stdout);
+        let result = entries
+            .iter()
+            .try_for_each(|entry| read_blob(&mut reader, entry, &mut visit));
+
+        // stop git early if the scan ended early, which also ends the writer thread
+        if result.is_err() {
+            let _ = child.kill();
+        }
+        result
+    });
+
+    finish(child, result)
+}
+
+/// Read one `<oid> blob <size>` response and pass exactly its contents to `visit`
+fn read_blob(
+    reader: &mut impl BufRead,
+    entry: &TreeEntry,
+    visit: &mut impl FnMut(&TreeEntry, &mut dyn Read) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let mut header = String::new();
+    reader.read_line(&mut header)?;
+    let header = header.trim_end();
+    if header != format!("{} blob {}", entry.oid, entry.size) {
+        return Err(anyhow::anyhow!("Unexpected git cat-file output: {header}"));
+    }
+
+    let mut contents = reader.take(entry.size);
+    visit(entry, &mut contents)?;
+
+    // skip anything the key didn't need, then the newline after the contents
+    std::io::copy(&mut contents, &mut std::io::sink())?;
+    reader.read_exact(&mut [0u8; 1])?;
+    Ok(())
+}
+
+/// Wait for git, so it isn't left as a zombie, and report a failure if the output was incomplete
+fn finish(mut child: Child, result: anyhow::Result<()>) -> anyhow::Result<()> {
+    let status = child.wait()?;
+    if result.is_ok() && !status.success() {
+        return Err(anyhow::anyhow!("git cat-file failed: {status}"));
+    }
+    result
+}
+
+/// Turn a hex object id into a key. Only SHA-1 ids match the blob ids computed for files
+fn oid_key(oid: &str) -> anyhow::Result<HashKey> {
+    if oid.len() != 40 {
+        return Err(anyhow::anyhow!(
+            "GitBlob comparison needs a SHA-1 repository, found object id '{oid}'"
+        ));
+    }
+
+    let bytes = (0..oid.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&oid[i..i + 2], 16))
+        .collect::<Result<Vec<u8>, _>>()?;
+    Ok(HashKey::new(&bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::hash_file_git_blob;
+    use crate::{cancel::CancelToken, filedata::IoStrategy};
+
+    /// A fresh repository with one commit, removed when dropped. None if git can't be run
+    struct TempRepo(PathBuf);
+
+    impl TempRepo {
+        fn new(name: &str, files: &[(&str, &str)]) -> Option<Self> {
+            let root = std::env::temp_dir()
+                .join(format!("folder_compare_git_{name}_{}", std::process::id()));
+            let _ = std::fs::remove_dir_all(&root);
+            std::fs::create_dir_all(&root).unwrap();
+            let repo = TempRepo(root);
+            for (path, contents) in files {
+                std::fs::write(repo.0.join(path), contents).unwrap();
+            }
+            let git = |args: &[&str]| {
+                Command::new("git")
+                    .arg("-C")
+                    .arg(&repo.0)
+                    .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+                    .args(args)
+                    .output()
+                    .is_ok_and(|output| output.status.success())
+            };
+            (git(&["init", "-q"]) && git(&["add", "."]) && git(&["commit", "-q", "-m", "test"]))
+                .then_some(repo)
+        }
+
+        fn source(&self, rev: &str) -> GitSource {
+            GitSource {
+                repo: self.0.clone(),
+                rev: rev.to_string(),
+            }
+        }
+    }
+
+    impl Drop for TempRepo {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn tree_keeps_regular_files() {
+        let output = b"100644 blob 45b983be36b73c0788dc9cbcb76cbb80fc7bb057       3\tdir/a file\0\
+100755 blob e69de29bb2d1d6434b8b29ae775ad8c2e48c5391       0\tname\twith\ntab\0\
+120000 blob 1de565933b05f74c75ff9a6520af5f9f8a5a2f1d       6\tlink\0\
+160000 commit 0123456789012345678901234567890123456789       -\tsubmodule\0";
+        let entries = parse_tree(output).unwrap();
+
+        let paths: Vec<&Path> = entries.iter().map(|e| e.path.as_path()).collect();
+        assert_eq!(
+            paths,
+            [Path::new("dir/a file"), Path::new("name\twith\ntab")]
+        );
+        assert_eq!(entries[0].oid, "45b983be36b73c0788dc9cbcb76cbb80fc7bb057");
+        assert_eq!(entries[0].size, 3);
+        assert_eq!(entries[1].size, 0);
+    }
+
+    #[test]
+    fn malformed_tree_is_an_error() {
+        assert!(
+            parse_tree(b"100644 blob 45b983be36b73c0788dc9cbcb76cbb80fc7bb057 3 no tab\0").is_err()
+        );
+        assert!(parse_tree(b"100644 blob 3\tmissing oid\0").is_err());
+        assert!(
+            parse_tree(b"100644 blob 45b983be36b73c0788dc9cbcb76cbb80fc7bb057 big\tf\0").is_err()
+        );
+    }
+
+    #[test]
+    fn oid_key_needs_sha1_hex() {
+        let key = oid_key("45b983be36b73c0788dc9cbcb76cbb80fc7bb057").unwrap();
+        assert_eq!(
+            key,
+            HashKey::new(&[
+                0x45, 0xb9, 0x83, 0xbe, 0x36, 0xb7, 0x3c, 0x07, 0x88, 0xdc, 0x9c, 0xbc, 0xb7, 0x6c,
+                0xbb, 0x80, 0xfc, 0x7b, 0xb0, 0x57
+            ])
+        );
+
+        let sha256 = "a".repeat(64);
+        assert!(oid_key(&sha256).is_err());
+        assert!(oid_key("zz983be36b73c0788dc9cbcb76cbb80fc7bb057").is_err());
+    }
+
+    #[test]
+    fn blob_ids_match_files_on_disk() {
+        let contents = "some contents\n";
+        let Some(repo) = TempRepo::new("blob", &[("f.txt", contents)]) else {
+            return;
+        };
+        let entries = list_tree(&repo.source("HEAD")).unwrap();
+        assert_eq!(entries.len(), 1);
+
+        let on_disk = hash_file_git_blob(
+            &repo.0.join("f.txt"),
+            IoStrategy::Auto,
+            None,
+            &CancelToken::new(),
+        )
+        .unwrap();
+        assert_eq!(oid_key(&entries[0].oid).unwrap(), on_disk);
+    }
+
+    #[test]
+    fn revision_is_never_an_option() {
+        let Some(repo) = TempRepo::new("option", &[("f.txt", "f")]) else {
+            return;
+        };
+        let written = repo.0.join("written");
+        let rev = format!("--output={}", written.display());
+
+        // git looks for a revision of that name, rather than rejecting an unknown option
+        let error = list_tree(&repo.source(&rev)).unwrap_err().to_string();
+        assert!(!error.contains("unknown option"), "{error}");
+        assert!(!written.exists());
+    }
+}
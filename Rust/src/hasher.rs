@@ -0,0 +1,57 @@
+use sha2::Digest;
+
+use crate::filedata::{HashAlgorithm, HashKey};
+
+/// Incremental hasher for any of the supported algorithms. An enum rather than a `Digest` generic,
+/// because BLAKE3 and xxh3 don't share the `Digest` trait and the algorithm is only known at runtime
+pub enum FileHasher {
+    Blake3(Box<blake3::Hasher>),
+    Sha256(sha2::Sha256),
+    Sha512(sha2::Sha512),
+    Sha1(sha1::Sha1),
+    Md5(md5::Md5),
+    Xxh3(Box<xxhash_rust::xxh3::Xxh3>),
+}
+
+impl FileHasher {
+    /// Create a new hasher for the given algorithm
+    #[must_use]
+    pub fn new(algorithm: HashAlgorithm) -> Self {
+        match algorithm {
+            HashAlgorithm::Blake3 => Self::Blake3(Box::default()),
+            HashAlgorithm::Sha256 => Self::Sha256(sha2::Sha256::new()),
+            HashAlgorithm::Sha512 => Self::Sha512(sha2::Sha512::new()),
+            HashAlgorithm::Sha1 => Self::Sha1(sha1::Sha1::new()),
+            HashAlgorithm::Md5 => Self::Md5(md5::Md5::new()),
+            HashAlgorithm::Xxh3 => Self::Xxh3(Box::default()),
+        }
+    }
+
+    /// Feed more data into the hasher
+    pub fn update(&mut self, data: impl AsRef<[u8]>) {
+        let data = data.as_ref();
+        match self {
+            Self::Blake3(h) => {
+                h.update(data);
+            }
+            Self::Sha256(h) => h.update(data),
+            Self::Sha512(h) => h.update(data),
+            Self::Sha1(h) => h.update(data),
+            Self::Md5(h) => h.update(data),
+            Self::Xxh3(h) => h.update(data),
+        }
+    }
+
+    /// Consume the hasher and return the digest as a `HashKey`
+    #[must_use]
+    pub fn finalize(self) -> HashKey {
+        match self {
+            Self::Blake3(h) => HashKey::new(h.finalize().as_bytes()),
+            Self::Sha256(h) => HashKey::new(&h.finalize()),
+            Self::Sha512(h) => HashKey::new(&h.finalize()),
+            Self::Sha1(h) => HashKey::new(&h.finalize()),
+            Self::Md5(h) => HashKey::new(&h.finalize()),
+            Self::Xxh3(h) => HashKey::new(&h.digest128().to_be_bytes()),
+        }
+    }
+}
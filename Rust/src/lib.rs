@@ -0,0 +1,45 @@
+#![forbid(unsafe_code)]
+
+//! Compare folders, archives and git revisions, finding the files present on one side but not the other.
+//!
+//! Files are keyed according to a `FileDataCompareOption` (name, name and size, or a hash of the contents), and
+//! two sides are compared by key. `ScanBuilder` is the simplest way in; `compare` takes a full `Config`, as the
+//! command line does.
+
+pub mod archive;
+pub mod cancel;
+pub mod comparer;
+pub mod dedupe;
+pub mod error;
+pub mod events;
+pub mod filedata;
+pub mod filelist;
+pub mod git;
+pub mod hasher;
+pub mod meta;
+pub mod namecheck;
+pub mod nway;
+pub mod progress;
+pub mod report;
+pub mod scan;
+pub mod script;
+pub mod summary;
+pub mod sync;
+pub mod utils;
+
+pub use archive::ArchiveKind;
+pub use comparer::{Comparer, FileComparer, FileData};
+pub use error::Error;
+pub use events::{Event, EventKind, EventSink};
+pub use filedata::{
+    ColorMode, DedupeMode, FileDataCompareOption, FilePath, HashAlgorithm, HashKey, IoStrategy,
+    MetaField, NameCase, NameCheckScope, NameNormalize, ScriptFormat, SyncPolicy, parse_algorithm,
+    parse_comparer, parse_io_strategy, parse_meta_fields, parse_name_case, parse_name_normalize,
+    parse_policy,
+};
+pub use git::GitSource;
+pub use progress::Progress;
+pub use scan::{
+    ComparisonResult, FolderScan, ScanBuilder, ScanOutput, compare, file_data, scan_folder,
+};
+pub use utils::Config;
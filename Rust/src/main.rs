@@ -3,81 +3,133 @@
 //#![allow(dead_code)]
 //#![allow(unused_variables)]
 
-#[allow(clippy::wildcard_imports)]
-use filedata::*;
-use std::{collections::HashMap, path::Path};
-#[allow(clippy::wildcard_imports)]
-use utils::*;
-use walkdir::WalkDir;
+use folder_compare::meta::{self, MetaMismatch};
+use folder_compare::namecheck::{self, InvalidName, TreePath};
+use folder_compare::nway::{self, MatrixRow};
+use folder_compare::progress::Progress;
+use folder_compare::report::{self, Report};
+use folder_compare::summary::{self, DiffTree, Totals};
+use folder_compare::sync::{self, SyncTarget};
+use folder_compare::utils::{folder_label, format_bytes, format_count, relative_path};
+use folder_compare::{
+    ComparisonResult, Config, FileData, FilePath, NameCheckScope, cancel, compare, dedupe, script,
+};
+use std::collections::BTreeSet;
+use std::path::{MAIN_SEPARATOR, Path};
+use std::time::Instant;
 
-mod filedata;
-mod utils;
+use cli::parse_args;
+use style::{Paint, Style};
+
+mod cli;
+mod style;
 
 fn main() -> anyhow::Result<()> {
     // parse the command line arguments
     let config = parse_args()?;
+    let started = Instant::now();
+    cancel::install_handler(config.cancel.clone())?;
+
+    if let Some(scope) = config.check_names {
+        check_names(&config, scope)?;
+        show_elapsed(&config, started);
+        if config.cancel.is_cancelled() {
+            std::process::exit(cancel::EXIT_INCOMPLETE);
+        }
+        return Ok(());
+    }
 
     // comparing a folder with itself is pointless
-    if config.folder1 == config.folder2 {
+    let roots = config.roots();
+    if roots
+        .iter()
+        .enumerate()
+        .any(|(i, root)| roots[..i].contains(root))
+    {
         return Err(anyhow::anyhow!("Folders should not be the same"));
     }
 
-    if !config.raw {
-        println!(
-            "Comparing folders '{}' and '{}'. Comparing by {:?}",
-            config.folder1.display(),
-            config.folder2.display(),
-            config.comparer
-        );
-        println!();
+    if roots.len() > 2 {
+        if !config.raw {
+            println!(
+                "Comparing {} folders. Comparing by {}",
+                roots.len(),
+                config.comparison_label()
+            );
+            println!();
+        }
+        scan_and_check_all(&config)?;
+    } else {
+        if !config.raw {
+            println!(
+                "Comparing folders '{}' and '{}'. Comparing by {}",
+                config.folder1.display(),
+                config.folder2.display(),
+                config.comparison_label()
+            );
+            println!();
+        }
+
+        scan_and_check(&config)?;
     }
+    show_elapsed(&config, started);
 
-    scan_and_check(&config)?;
+    if config.cancel.is_cancelled() {
+        std::process::exit(cancel::EXIT_INCOMPLETE);
+    }
 
     Ok(())
 }
 
 /// Wrapper around main scanning and comparison. Only needed because this is generic over the comparison type U
 fn scan_and_check(config: &Config) -> anyhow::Result<()> {
-    // create the hashsets
-    let files1;
-    let files2;
-
-    // scan the folders and populate the HashSets
-    if config.one_thread {
-        // scan the two folders in series, using one thread
-        files1 = scan_folder(config, &config.folder1)?;
-        files2 = scan_folder(config, &config.folder2)?;
-    } else {
-        // scan them in parallel
-        let (res_files_1, res_files_2) = rayon::join(
-            || scan_folder(config, &config.folder1),
-            || scan_folder(config, &config.folder2),
-        );
+    let progress = Progress::new(config.show_eta(), 2);
 
-        files1 = res_files_1?;
-        files2 = res_files_2?;
-    }
+    // scan and verify while the progress display (if any) runs on its own thread
+    let result = progress.run_with_display(config.raw, || {
+        compare(config, &progress).map_err(anyhow::Error::from)
+    })?;
+
+    warn_if_cancelled(config);
 
-    // find what's in files1, but not in files2
-    let diff1 = hashmap_difference(&files1, &files2);
-    show_results(&diff1, &config.folder1, &config.folder2, config.raw);
+    // find what's in A but not B, including QuickHash matches that failed verification. Every file is acted on,
+    // so duplicates sharing a key with another file on the same side are included
+    let diff1: Vec<&FilePath> = result.all_only_in_a().collect();
 
-    // count the differences
-    let count = if config.first_only {
-        // we don't care about the second stage, just yield the first count
-        diff1.len()
+    // find what's in B, but not in A. Skipped if we only care about the first stage
+    let diff2: Vec<&FilePath> = if config.first_only {
+        Vec::new()
     } else {
-        // find what's in files2, but not in files1
-        let diff2 = hashmap_difference(&files2, &files1);
-        show_results(&diff2, &config.folder2, &config.folder1, config.raw);
+        result.all_only_in_b().collect()
+    };
 
-        // yield both counts
-        diff1.len() + diff2.len()
+    // a script replaces the normal results on stdout
+    if let Some(format) = config.emit_script {
+        if config.cancel.is_cancelled() {
+            return Err(anyhow::anyhow!("Scan was cancelled, no script written"));
+        }
+        let actions = sync::plan_policy(config, config.policy, &diff1, &diff2)?;
+        sync::check_delete_limit(&sync::PlanSummary::new(&actions), config.max_delete)?;
+        return script::write_script(
+            &mut std::io::stdout().lock(),
+            format,
+            config.policy,
+            &actions,
+        );
+    }
+
+    // files at the same relative path can still differ in permissions, owners, times or attributes
+    let mismatches = if config.check_meta.is_empty() {
+        Vec::new()
+    } else {
+        meta::compare_meta(config, &result.scan.scan1, &result.scan.scan2)
     };
 
-    if !config.raw {
-        println!("{count} difference(s) found");
+    if config.raw {
+        show_raw_results(config, &result, &mismatches);
+    } else {
+        let categories = Report::new(config, &result, &mismatches);
+        show_results(config, &Style::new(config.color), &categories);
 
         // *** hashset stats ***
         // let lbs1 = files1.largest_bucket_size();
@@ -91,60 +143,505 @@ fn scan_and_check(config: &Config) -> anyhow::Result<()> {
         // println!("Folder2: {size2} files, largest bucket size {lbs2}, empty buckets {empty2}");
     }
 
+    if let Some(path) = &config.report {
+        report::write_report(config, &result, &mismatches, path)?;
+        if !config.raw {
+            println!("Report written to '{}'", path.display());
+        }
+    }
+
+    run_sync_actions(config, &diff1, &diff2)?;
+
+    if let Some(mode) = config.dedupe {
+        if config.cancel.is_cancelled() {
+            eprintln!("Scan was cancelled, no files deduplicated");
+        } else {
+            dedupe::run(config, mode, &result.scan)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Compare more than two folders, reporting which folders have each file
+fn scan_and_check_all(config: &Config) -> anyhow::Result<()> {
+    let roots = config.roots();
+    let progress = Progress::new(config.show_eta(), roots.len());
+
+    let scans =
+        progress.run_with_display(config.raw, || nway::scan_all(config, &roots, &progress))?;
+    warn_if_cancelled(config);
+
+    let (rows, everywhere) = nway::presence_matrix(&scans);
+    show_matrix(&roots, &rows, config.raw);
+
+    if !config.raw {
+        println!(
+            "{everywhere} file(s) present in all {} folders",
+            roots.len()
+        );
+        println!("{} difference(s) found", rows.len());
+    }
+
+    Ok(())
+}
+
+/// Report names that would clash on a case-insensitive, normalising filesystem, or can't be created on Windows
+fn check_names(config: &Config, scope: NameCheckScope) -> anyhow::Result<()> {
+    // a single tree is given as both A and B
+    let mut roots = config.roots();
+    roots.dedup();
+
+    if !config.raw {
+        let within = match scope {
+            NameCheckScope::Tree => "within each folder",
+            NameCheckScope::Union => "across all the folders together",
+        };
+        println!("Checking names in {} folder(s), {within}", roots.len());
+        println!();
+    }
+
+    let progress = Progress::new(false, roots.len());
+    let scans =
+        progress.run_with_display(config.raw, || nway::scan_all(config, &roots, &progress))?;
+    warn_if_cancelled(config);
+
+    let trees: Vec<BTreeSet<TreePath>> = roots
+        .iter()
+        .zip(&scans)
+        .enumerate()
+        .map(|(side, (root, scan))| namecheck::tree_paths(root, scan, side))
+        .collect();
+
+    let collisions = match scope {
+        NameCheckScope::Tree => trees.iter().flat_map(namecheck::find_collisions).collect(),
+        NameCheckScope::Union => {
+            namecheck::find_collisions(&trees.iter().flatten().cloned().collect())
+        }
+    };
+    let invalid: Vec<InvalidName> = trees.iter().flat_map(namecheck::find_invalid).collect();
+
+    show_name_problems(&roots, &collisions, &invalid, config.raw);
     Ok(())
 }
 
-/// Show the results of the comparison
-fn show_results(
-    differences: &Vec<&FilePath>,
-    present_in_dir: &Path,
-    absent_in_dir: &Path,
+/// Show each group of clashing names, then each name invalid on Windows
+fn show_name_problems(
+    roots: &[&Path],
+    collisions: &[Vec<TreePath>],
+    invalid: &[InvalidName],
     raw: bool,
 ) {
-    if !raw {
+    if raw {
+        // each clashing group is numbered, so the groups can be told apart
+        for (group, paths) in collisions.iter().enumerate() {
+            for path in paths {
+                println!(
+                    "clash\t{}\t{}\t{}",
+                    group + 1,
+                    folder_label(path.side),
+                    path.path.display()
+                );
+            }
+        }
+        for name in invalid {
+            println!(
+                "invalid\t{}\t{}\t{}",
+                folder_label(name.path.side),
+                name.path.path.display(),
+                name.reason
+            );
+        }
+        return;
+    }
+
+    for (side, root) in roots.iter().enumerate() {
+        println!("{}: {}", folder_label(side), root.display());
+    }
+    println!();
+
+    println!("Names that clash when case and Unicode form are ignored");
+    if collisions.is_empty() {
+        println!("None");
+    }
+    for group in collisions {
+        for path in group {
+            println!("{}: {}", folder_label(path.side), path.path.display());
+        }
+        println!();
+    }
+    if collisions.is_empty() {
+        println!();
+    }
+
+    println!("Names that are invalid on Windows");
+    if invalid.is_empty() {
+        println!("None");
+    }
+    for name in invalid {
         println!(
-            "Files in '{}' but not in '{}'",
-            present_in_dir.display(),
-            absent_in_dir.display()
+            "{}: {}  ({})",
+            folder_label(name.path.side),
+            name.path.path.display(),
+            name.reason
         );
-        if differences.is_empty() {
-            println!("None");
+    }
+    println!();
+
+    println!("{} problem(s) found", collisions.len() + invalid.len());
+}
+
+/// A cancelled scan still reports what it found, but clearly marked
+fn warn_if_cancelled(config: &Config) {
+    if config.cancel.is_cancelled() {
+        eprintln!("Scan cancelled, results are INCOMPLETE");
+        if !config.raw {
+            println!("*** INCOMPLETE: scan was cancelled, differences below are only partial ***");
+            println!();
+        }
+    }
+}
+
+/// Copy missing files or mirror, if requested, after the results have been shown
+fn run_sync_actions(
+    config: &Config,
+    diff1: &[&FilePath],
+    diff2: &[&FilePath],
+) -> anyhow::Result<()> {
+    // acting on a partial scan could duplicate or delete files that simply weren't reached yet
+    if config.cancel.is_cancelled() {
+        if config.copy_missing_to_b || config.copy_missing_to_a || config.mirror {
+            eprintln!("Scan was cancelled, no files changed");
+        }
+        return Ok(());
+    }
+
+    let mut failures = 0;
+    if config.copy_missing_to_b {
+        let actions = sync::plan_copy_missing(diff1, &config.folder1, &config.folder2)?;
+        let target = SyncTarget {
+            root: &config.folder2,
+            quarantine: None,
+        };
+        failures += sync::execute(config, &actions, config.dry_run, &target);
+    }
+    if config.copy_missing_to_a {
+        let actions = sync::plan_copy_missing(diff2, &config.folder2, &config.folder1)?;
+        let target = SyncTarget {
+            root: &config.folder1,
+            quarantine: None,
+        };
+        failures += sync::execute(config, &actions, config.dry_run, &target);
+    }
+    if config.mirror {
+        let actions = sync::plan_mirror(config, &config.folder1, &config.folder2)?;
+        let summary = sync::PlanSummary::new(&actions);
+        sync::check_delete_limit(&summary, config.max_delete)?;
+
+        let target = SyncTarget {
+            root: &config.folder2,
+            quarantine: (!config.no_quarantine).then(|| {
+                config
+                    .quarantine
+                    .clone()
+                    .unwrap_or_else(|| sync::default_quarantine(&config.folder2))
+            }),
+        };
+
+        if !config.dry_run && !config.yes && !sync::confirm(&summary, &target)? {
+            return Err(anyhow::anyhow!("Mirror not confirmed, nothing changed"));
         }
+        failures += sync::execute(config, &actions, config.dry_run, &target);
+    }
+    if failures > 0 {
+        return Err(anyhow::anyhow!("{failures} sync action(s) failed"));
     }
-    for f in differences {
+
+    Ok(())
+}
+
+/// Raw results, for piping: the full path of each file with no match, then a tab separated line per metadata
+/// difference
+fn show_raw_results(config: &Config, result: &ComparisonResult, mismatches: &[MetaMismatch]) {
+    show_raw_side(
+        config,
+        result.folders_only_in_a(),
+        result.only_in_a(),
+        result.only_in_a_collapsed(),
+    );
+    if !config.first_only {
+        show_raw_side(
+            config,
+            result.folders_only_in_b(),
+            result.only_in_b(),
+            result.only_in_b_collapsed(),
+        );
+    }
+    for m in mismatches {
+        println!("meta\t{}\t{}\t{}\t{}", m.path.display(), m.field, m.a, m.b);
+    }
+}
+
+/// One side's raw lines, one file per line as the C# version prints. With `--raw-folders`, folders only on this
+/// side come first, marked with a trailing separator, and stand for the files inside them unless expanded
+fn show_raw_side<'a>(
+    config: &Config,
+    folders: impl Iterator<Item = &'a FilePath>,
+    files: impl Iterator<Item = &'a FilePath>,
+    collapsed: impl Iterator<Item = &'a FilePath>,
+) {
+    if !config.raw_folders {
+        files.for_each(|f| println!("{f}"));
+        return;
+    }
+
+    for folder in folders {
+        println!("{folder}{MAIN_SEPARATOR}");
+    }
+    let shown: Box<dyn Iterator<Item = &FilePath>> = if config.expand {
+        Box::new(files)
+    } else {
+        Box::new(collapsed)
+    };
+    for f in shown {
         println!("{f}");
     }
-    if !raw {
-        println!();
+}
+
+/// Show the differences for reading: what is only on each side, what changed, and a summary table
+fn show_results(config: &Config, style: &Style, report: &Report) {
+    let roots = (config.folder1.as_path(), config.folder2.as_path());
+    let mut count = show_side(
+        config,
+        style,
+        Paint::OnlyInA,
+        roots,
+        &report.folders_only_a,
+        &report.only_a,
+    );
+    if !config.first_only {
+        count += show_side(
+            config,
+            style,
+            Paint::OnlyInB,
+            (roots.1, roots.0),
+            &report.folders_only_b,
+            &report.only_b,
+        );
+    }
+    show_changed(config, style, &report.changed);
+    if !config.check_meta.is_empty() {
+        show_meta_mismatches(config, style, report.metadata);
+    }
+
+    show_summary(config, style, report);
+    println!("{} difference(s) found", count + report.changed.len());
+}
+
+/// Show the differences only on one side, returning how many there are, each folder counting once. Folders
+/// come first, marked with a trailing separator and followed by the totals of the files inside
+fn show_side(
+    config: &Config,
+    style: &Style,
+    paint: Paint,
+    (root, other_root): (&Path, &Path),
+    folders: &[&FilePath],
+    files: &[&FileData],
+) -> usize {
+    // a folder only on one side stands for everything inside it, unless every file is wanted
+    let shown: Vec<&FileData> = files
+        .iter()
+        .copied()
+        .filter(|file| {
+            config.expand
+                || !folders
+                    .iter()
+                    .any(|folder| file.path.0.starts_with(&folder.0))
+        })
+        .collect();
+
+    let heading = format!(
+        "Files and folders in '{}' but not in '{}'",
+        root.display(),
+        other_root.display()
+    );
+    println!("{}", style.paint(Paint::Heading, heading));
+    if folders.is_empty() && shown.is_empty() {
+        println!("None");
+    }
+
+    if config.tree {
+        for line in DiffTree::new(root, folders, files, config.expand).lines() {
+            println!("{}", style.paint(paint, line));
+        }
+    } else {
+        let totals = summary::folder_totals(folders, files.iter().copied());
+        for (folder, totals) in folders.iter().zip(totals) {
+            let line = format!(
+                "{}{MAIN_SEPARATOR} ({totals})",
+                shown_path(config, root, &folder.0)
+            );
+            println!("{}", style.paint(paint, line));
+        }
+        for file in &shown {
+            let line = format!(
+                "{} ({})",
+                shown_path(config, root, &file.path.0),
+                format_bytes(file.size)
+            );
+            println!("{}", style.paint(paint, line));
+        }
+    }
+    println!();
+    folders.len() + shown.len()
+}
+
+/// Show the files at the same relative path on both sides that don't match, with their sizes in A and B
+fn show_changed(config: &Config, style: &Style, changed: &[(&FileData, &FileData)]) {
+    let heading = format!(
+        "Files changed between '{}' and '{}'",
+        config.folder1.display(),
+        config.folder2.display()
+    );
+    println!("{}", style.paint(Paint::Heading, heading));
+    if changed.is_empty() {
+        println!("None");
+    }
+    for (a, b) in changed {
+        let line = format!(
+            "{} ({} -> {})",
+            shown_path(config, &config.folder1, &a.path.0),
+            format_bytes(a.size),
+            format_bytes(b.size)
+        );
+        println!("{}", style.paint(Paint::Changed, line));
     }
+    println!();
 }
 
-/// Scan a folder and build hashset with the files
-fn scan_folder(config: &Config, dir: &Path) -> anyhow::Result<HashMap<Sha2Hash, FilePath>> {
-    let mut fileset: HashMap<Sha2Hash, FilePath> = HashMap::with_capacity(200);
+/// Show each metadata difference, with the value in A then the value in B
+fn show_meta_mismatches(config: &Config, style: &Style, mismatches: &[MetaMismatch]) {
+    let heading = "Files whose metadata differs (A -> B)";
+    println!("{}", style.paint(Paint::Heading, heading));
+    if mismatches.is_empty() {
+        println!("None");
+    }
+    for m in mismatches {
+        let path = shown_path(config, &config.folder1, &config.folder1.join(&m.path));
+        let line = format!("{path}  {}: {} -> {}", m.field, m.a, m.b);
+        println!("{}", style.paint(Paint::Changed, line));
+    }
+    println!();
+}
 
-    for entry in WalkDir::new(dir).into_iter().filter_map(Result::ok) {
-        if entry.file_type().is_file() {
-            let file_path = entry.path().to_str().unwrap();
+/// A table of the folders, files and bytes in each category
+fn show_summary(config: &Config, style: &Style, report: &Report) {
+    let mut rows = vec![(
+        "Only in A",
+        Paint::OnlyInA,
+        Some(report.folders_only_a.len()),
+        Totals::of(report.only_a.iter().copied()),
+    )];
+    if !config.first_only {
+        rows.push((
+            "Only in B",
+            Paint::OnlyInB,
+            Some(report.folders_only_b.len()),
+            Totals::of(report.only_b.iter().copied()),
+        ));
+    }
+    rows.push((
+        "Changed",
+        Paint::Changed,
+        None,
+        Totals::of(report.changed.iter().map(|(a, _)| *a)),
+    ));
 
-            // generate the SHA2 key according to the comparison option
-            let key = match config.comparer {
-                FileDataCompareOption::Name => {
-                    let file_name = entry.file_name().to_str().unwrap();
-                    hash_string::<sha2::Sha256>(file_name)
-                }
-                FileDataCompareOption::NameSize => {
-                    let file_name = entry.file_name().to_str().unwrap();
-                    let file_size = entry.metadata()?.len();
-                    hash_string_and_size::<sha2::Sha256>(file_name, file_size)
-                }
-                FileDataCompareOption::Hash => hash_file::<sha2::Sha256>(file_path)?,
-            };
+    // labels are padded before they are painted, so colour codes don't upset the columns
+    println!("{}", style.paint(Paint::Heading, "Summary"));
+    println!("{:<12} {:>9} {:>9} {:>10}", "", "Folders", "Files", "Bytes");
+    for (label, paint, folders, totals) in rows {
+        println!(
+            "{} {:>9} {:>9} {:>10}",
+            style.paint(paint, format!("{label:<12}")),
+            folders.map(format_count).unwrap_or_default(),
+            format_count(totals.files),
+            format_bytes(totals.bytes)
+        );
+    }
+    if !config.check_meta.is_empty() {
+        println!(
+            "{} {:>9} {:>9}",
+            style.paint(Paint::Changed, format!("{:<12}", "Metadata")),
+            "",
+            format_count(report.metadata.len())
+        );
+    }
+    println!();
+}
 
-            // insert the file into the hashset, with required key
-            fileset.insert(key, file_path.into());
+/// A path as shown: relative to its folder, unless full paths were asked for
+fn shown_path(config: &Config, root: &Path, path: &Path) -> String {
+    if config.full_paths {
+        path.display().to_string()
+    } else {
+        relative_path(root, path).display().to_string()
+    }
+}
+
+/// How long the whole run took, unless the output is for piping
+fn show_elapsed(config: &Config, started: Instant) {
+    if !config.raw {
+        println!("Finished in {:.2}s", started.elapsed().as_secs_f64());
+    }
+}
+
+/// Show one line per key: an X for each folder that has it, the file, and which folders lack it
+fn show_matrix(roots: &[&Path], rows: &[MatrixRow], raw: bool) {
+    let labels: Vec<String> = (0..roots.len()).map(folder_label).collect();
+    let width = labels.iter().map(String::len).max().unwrap_or(1);
+
+    if !raw {
+        for (label, root) in labels.iter().zip(roots) {
+            println!("{label:>width$}: {}", root.display());
+        }
+        println!();
+        println!("Presence matrix (X present, - missing)");
+        let header: Vec<String> = labels.iter().map(|l| format!("{l:>width$}")).collect();
+        println!("{}", header.join(" "));
+        if rows.is_empty() {
+            println!("None");
         }
     }
 
-    Ok(fileset)
+    for row in rows {
+        let cells: Vec<String> = row
+            .present
+            .iter()
+            .map(|p| format!("{:>width$}", if p.is_some() { "X" } else { "-" }))
+            .collect();
+
+        if raw {
+            println!("{}\t{}", cells.concat().replace(' ', ""), row.file());
+        } else {
+            let missing: Vec<&str> = labels
+                .iter()
+                .zip(&row.present)
+                .filter(|(_, p)| p.is_none())
+                .map(|(label, _)| label.as_str())
+                .collect();
+            println!(
+                "{} {}  (present in {} of {}, missing from {})",
+                cells.join(" "),
+                row.file(),
+                row.count(),
+                roots.len(),
+                missing.join(", ")
+            );
+        }
+    }
+
+    if !raw {
+        println!();
+    }
 }
@@ -0,0 +1,405 @@
+use std::collections::BTreeMap;
+use std::fs::Metadata;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::comparer::NameForm;
+use crate::filedata::{HashKey, MetaField};
+use crate::scan::FolderScan;
+use crate::utils::{Config, format_time};
+
+/// The extended attributes Linux keeps ACLs in, compared as `acl` rather than `xattr`
+const ACL_ACCESS: &str = "system.posix_acl_access";
+const ACL_PREFIX: &str = "system.posix_acl_";
+
+/// Longest attribute value shown in full
+const MAX_VALUE_CHARS: usize = 64;
+
+/// A difference in metadata between files at the same relative path
+#[derive(Debug)]
+pub struct MetaMismatch {
+    pub path: PathBuf, // path relative to the roots, as spelled in A
+    pub field: String, // what differs, eg `mode` or `xattr user.comment`
+    pub a: String,     // value in A
+    pub b: String,     // value in B
+}
+
+/// Can this platform read a metadata field?
+#[must_use]
+pub fn supported(field: MetaField) -> bool {
+    match field {
+        MetaField::Mtime => true,
+        MetaField::Mode | MetaField::Uid | MetaField::Gid | MetaField::Xattr => cfg!(unix),
+        MetaField::Acl => cfg!(target_os = "linux"),
+    }
+}
+
+/// Compare the `--check-meta` fields of every file found at the same relative path on both sides. Files that
+/// can't be read are reported on stderr and skipped
+#[must_use]
+pub fn compare_meta(config: &Config, scan1: &FolderScan, scan2: &FolderScan) -> Vec<MetaMismatch> {
+    let form = config.name_form();
+    let files1 = relative_files(&config.folder1, scan1, form);
+    let files2 = relative_files(&config.folder2, scan2, form);
+
+    let mut mismatches = Vec::new();
+    for (key, (relative, path1)) in &files1 {
+        if config.cancel.is_cancelled() {
+            break;
+        }
+        let Some((_, path2)) = files2.get(key) else {
+            continue;
+        };
+
+        let (metadata1, metadata2) = match (std::fs::metadata(path1), std::fs::metadata(path2)) {
+            (Ok(metadata1), Ok(metadata2)) => (metadata1, metadata2),
+            (Err(e), _) | (_, Err(e)) => {
+                let message = format!("Skipping metadata of '{}': {e}", relative.display());
+                config.warn(None, relative, message);
+                continue;
+            }
+        };
+        let pair = FilePair {
+            paths: [path1, path2],
+            metadata: [&metadata1, &metadata2],
+        };
+
+        for &field in &config.check_meta {
+            match pair.differences(field, config.mtime_tolerance) {
+                Ok(differences) => {
+                    mismatches.extend(differences.into_iter().map(|(name, a, b)| MetaMismatch {
+                        path: relative.clone(),
+                        field: name,
+                        a,
+                        b,
+                    }));
+                }
+                Err(e) => {
+                    let message = format!("Skipping {field:?} of '{}': {e}", relative.display());
+                    config.warn(None, relative, message);
+                }
+            }
+        }
+    }
+    mismatches
+}
+
+/// Files on disk by folded relative path, with the path as spelled. Archive and git members have no metadata
+/// of their own, so they never appear
+fn relative_files(
+    root: &Path,
+    scan: &FolderScan,
+    form: NameForm,
+) -> BTreeMap<PathBuf, (PathBuf, PathBuf)> {
+    scan.iter()
+        .chain(&scan.duplicates)
+        .filter_map(|file| {
+            let relative = file.path.0.strip_prefix(root).ok()?;
+            Some((
+                form.apply_path(relative),
+                (relative.to_path_buf(), file.path.0.clone()),
+            ))
+        })
+        .collect()
+}
+
+/// The same relative path on both sides
+struct FilePair<'a> {
+    paths: [&'a Path; 2],
+    metadata: [&'a Metadata; 2],
+}
+
+impl FilePair<'_> {
+    /// What differs in one field, as (name, value in A, value in B). Xattrs can differ in several attributes
+    fn differences(
+        &self,
+        field: MetaField,
+        tolerance: Duration,
+    ) -> std::io::Result<Vec<(String, String, String)>> {
+        let name = format!("{field:?}").to_lowercase();
+        let differs = |a: String, b: String| {
+            if a == b {
+                vec![]
+            } else {
+                vec![(name.clone(), a, b)]
+            }
+        };
+
+        Ok(match field {
+            MetaField::Mode => differs(unix::mode(self.metadata[0]), unix::mode(self.metadata[1])),
+            MetaField::Uid => differs(unix::uid(self.metadata[0]), unix::uid(self.metadata[1])),
+            MetaField::Gid => differs(unix::gid(self.metadata[0]), unix::gid(self.metadata[1])),
+            MetaField::Mtime => {
+                let a = self.metadata[0].modified()?;
+                let b = self.metadata[1].modified()?;
+                if within(a, b, tolerance) {
+                    vec![]
+                } else {
+                    vec![(name, format_time(a), format_time(b))]
+                }
+            }
+            MetaField::Xattr => {
+                let a = unix::xattrs(self.paths[0])?;
+                let b = unix::xattrs(self.paths[1])?;
+                let mut names: Vec<&String> = a.keys().chain(b.keys()).collect();
+                names.sort_unstable();
+                names.dedup();
+                names
+                    .into_iter()
+                    .filter(|attribute| a.get(*attribute) != b.get(*attribute))
+                    .map(|attribute| {
+                        let value = |values: &BTreeMap<String, Vec<u8>>| {
+                            values
+                                .get(attribute)
+                                .map_or_else(|| "(none)".to_string(), |v| format_value(v))
+                        };
+                        (format!("xattr {attribute}"), value(&a), value(&b))
+                    })
+                    .collect()
+            }
+            MetaField::Acl => differs(unix::acl(self.paths[0])?, unix::acl(self.paths[1])?),
+        })
+    }
+}
+
+/// Are two times no more than `tolerance` apart, whichever is earlier?
+fn within(a: SystemTime, b: SystemTime, tolerance: Duration) -> bool {
+    let apart = a.duration_since(b).or_else(|_| b.duration_since(a));
+    apart.is_ok_and(|apart| apart <= tolerance)
+}
+
+/// An attribute value as text if it is printable, otherwise as hex. Long values are cut short
+fn format_value(value: &[u8]) -> String {
+    let text = std::str::from_utf8(value.strip_suffix(b"\0").unwrap_or(value))
+        .ok()
+        .filter(|text| !text.chars().any(char::is_control));
+
+    let shown = match text {
+        Some(text) => format!("\"{text}\""),
+        None => HashKey::new(value).to_string(),
+    };
+    if shown.chars().count() <= MAX_VALUE_CHARS {
+        return shown;
+    }
+    let start: String = shown.chars().take(MAX_VALUE_CHARS).collect();
+    format!("{start}... ({} bytes)", value.len())
+}
+
+/// Linux stores an ACL as a version, then 8 byte entries of tag, permissions and id, all little endian.
+/// Shown in the short form `getfacl -c` uses, eg `user::rw-,user:1000:r--,group::r--,mask::r--,other::r--`
+fn format_acl(value: &[u8]) -> String {
+    let entries = value.get(4..).unwrap_or_default();
+    if value.get(..4) != Some(&2u32.to_le_bytes()[..]) || entries.len() % 8 != 0 {
+        return format_value(value);
+    }
+
+    entries
+        .chunks_exact(8)
+        .map(|entry| {
+            let tag = u16::from_le_bytes([entry[0], entry[1]]);
+            let perm = u16::from_le_bytes([entry[2], entry[3]]);
+            let id = u32::from_le_bytes([entry[4], entry[5], entry[6], entry[7]]);
+            let qualifier = match tag {
+                0x02 | 0x08 => id.to_string(),
+                _ => String::new(),
+            };
+            let kind = match tag {
+                0x01 | 0x02 => "user",
+                0x04 | 0x08 => "group",
+                0x10 => "mask",
+                0x20 => "other",
+                _ => "unknown",
+            };
+            let bits: String = [(4, 'r'), (2, 'w'), (1, 'x')]
+                .iter()
+                .map(|&(bit, c)| if perm & bit == 0 { '-' } else { c })
+                .collect();
+            format!("{kind}:{qualifier}:{bits}")
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+#[cfg(unix)]
+mod unix {
+    use std::collections::BTreeMap;
+    use std::fs::Metadata;
+    use std::os::unix::fs::MetadataExt;
+    use std::path::Path;
+
+    use super::{ACL_ACCESS, ACL_PREFIX, format_acl};
+
+    pub fn mode(metadata: &Metadata) -> String {
+        format!("{:04o}", metadata.mode() & 0o7777)
+    }
+
+    pub fn uid(metadata: &Metadata) -> String {
+        metadata.uid().to_string()
+    }
+
+    pub fn gid(metadata: &Metadata) -> String {
+        metadata.gid().to_string()
+    }
+
+    /// Extended attributes by name, leaving out the ones holding ACLs
+    pub fn xattrs(path: &Path) -> std::io::Result<BTreeMap<String, Vec<u8>>> {
+        let mut attributes = BTreeMap::new();
+        for name in xattr::list_deref(path)? {
+            let text = name.to_string_lossy();
+            if text.starts_with(ACL_PREFIX) {
+                continue;
+            }
+            if let Some(value) = xattr::get_deref(path, &name)? {
+                attributes.insert(text.into_owned(), value);
+            }
+        }
+        Ok(attributes)
+    }
+
+    /// The file's access ACL. Files without one only have the permissions in their mode
+    pub fn acl(path: &Path) -> std::io::Result<String> {
+        Ok(xattr::get_deref(path, ACL_ACCESS)?
+            .map_or_else(|| "(none)".to_string(), |value| format_acl(&value)))
+    }
+}
+
+/// Only modification times can be compared elsewhere, which `supported` checks before a comparison starts
+#[cfg(not(unix))]
+mod unix {
+    use std::collections::BTreeMap;
+    use std::fs::Metadata;
+    use std::path::Path;
+
+    fn unsupported() -> std::io::Error {
+        std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "not supported on this platform",
+        )
+    }
+
+    pub fn mode(_: &Metadata) -> String {
+        String::new()
+    }
+
+    pub fn uid(_: &Metadata) -> String {
+        String::new()
+    }
+
+    pub fn gid(_: &Metadata) -> String {
+        String::new()
+    }
+
+    pub fn xattrs(_: &Path) -> std::io::Result<BTreeMap<String, Vec<u8>>> {
+        Err(unsupported())
+    }
+
+    pub fn acl(_: &Path) -> std::io::Result<String> {
+        Err(unsupported())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An ACL of `user::rw-,user:1000:r--,group::r--,mask::r--,other::r--`
+    fn acl_bytes() -> Vec<u8> {
+        let mut value = 2u32.to_le_bytes().to_vec();
+        for (tag, perm, id) in [
+            (0x01u16, 6u16, u32::MAX),
+            (0x02, 4, 1000),
+            (0x04, 4, u32::MAX),
+            (0x10, 4, u32::MAX),
+            (0x20, 4, u32::MAX),
+        ] {
+            value.extend(tag.to_le_bytes());
+            value.extend(perm.to_le_bytes());
+            value.extend(id.to_le_bytes());
+        }
+        value
+    }
+
+    #[test]
+    fn acl_in_getfacl_short_form() {
+        assert_eq!(
+            format_acl(&acl_bytes()),
+            "user::rw-,user:1000:r--,group::r--,mask::r--,other::r--"
+        );
+    }
+
+    #[test]
+    fn malformed_acl_is_shown_raw() {
+        let acl = acl_bytes();
+
+        // every truncation, not just whole entries, and an empty value
+        for len in 0..acl.len() {
+            let prefix = &acl[..len];
+            if len >= 4 && (len - 4) % 8 == 0 {
+                let entries = format_acl(prefix);
+                let count = entries.split(',').filter(|entry| !entry.is_empty()).count();
+                assert_eq!(count, (len - 4) / 8, "{len}");
+            } else {
+                assert_eq!(format_acl(prefix), format_value(prefix), "{len}");
+            }
+        }
+
+        let mut wrong_version = acl.clone();
+        wrong_version[0] = 1;
+        assert_eq!(format_acl(&wrong_version), format_value(&wrong_version));
+
+        let mut unknown_tag = acl;
+        unknown_tag[4] = 0x40;
+        assert!(format_acl(&unknown_tag).starts_with("unknown::rw-,"));
+    }
+
+    #[test]
+    fn values_shown_as_text_or_hex() {
+        assert_eq!(format_value(b"hello\0"), "\"hello\"");
+        assert_eq!(format_value(&[0xff, 0x00, 0x10]), "ff0010");
+
+        let long = format_value(&[b'x'; 100]);
+        assert!(long.ends_with("... (100 bytes)"), "{long}");
+    }
+
+    #[test]
+    fn mtime_tolerance_works_both_ways() {
+        let a = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let b = a + Duration::from_millis(1500);
+
+        assert!(within(a, a, Duration::ZERO));
+        assert!(!within(a, b, Duration::ZERO));
+        assert!(within(a, b, Duration::from_secs(2)));
+        assert!(within(b, a, Duration::from_secs(2)));
+        assert!(!within(b, a, Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn mtime_differences_respect_the_tolerance() {
+        let folder =
+            std::env::temp_dir().join(format!("folder_compare_meta_{}", std::process::id()));
+        std::fs::create_dir_all(&folder).unwrap();
+        let (a, b) = (folder.join("a"), folder.join("b"));
+        std::fs::write(&a, "a").unwrap();
+        std::fs::write(&b, "b").unwrap();
+        filetime::set_file_mtime(&a, filetime::FileTime::from_unix_time(1_000_000, 0)).unwrap();
+        filetime::set_file_mtime(&b, filetime::FileTime::from_unix_time(1_000_001, 0)).unwrap();
+
+        let (metadata_a, metadata_b) = (
+            std::fs::metadata(&a).unwrap(),
+            std::fs::metadata(&b).unwrap(),
+        );
+        let pair = FilePair {
+            paths: [&a, &b],
+            metadata: [&metadata_a, &metadata_b],
+        };
+        let strict = pair.differences(MetaField::Mtime, Duration::ZERO).unwrap();
+        let tolerant = pair
+            .differences(MetaField::Mtime, Duration::from_secs(2))
+            .unwrap();
+        let _ = std::fs::remove_dir_all(&folder);
+
+        assert_eq!(strict.len(), 1);
+        assert_eq!(strict[0].0, "mtime");
+        assert!(tolerant.is_empty());
+    }
+}
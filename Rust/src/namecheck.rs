@@ -0,0 +1,193 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+
+use crate::comparer::NameForm;
+use crate::filedata::{NameCase, NameNormalize};
+use crate::scan::FolderScan;
+use crate::utils::relative_path;
+
+/// Names Windows reserves for devices, with or without an extension
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Characters Windows doesn't allow in names, besides control characters
+const RESERVED_CHARS: [char; 8] = ['<', '>', ':', '"', '\\', '|', '?', '*'];
+
+/// How a case-insensitive, normalising filesystem such as NTFS or APFS sees a name
+const FOLDED: NameForm = NameForm {
+    case: NameCase::Insensitive,
+    normalize: NameNormalize::Nfc,
+};
+
+/// A path relative to one of the roots being checked
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TreePath {
+    pub side: usize,   // index of the root, 0 for A
+    pub path: PathBuf, // path relative to the root
+}
+
+/// A name that can't be created on Windows, and why
+#[derive(Debug)]
+pub struct InvalidName {
+    pub path: TreePath,
+    pub reason: String,
+}
+
+/// Every file in a scan, and every folder above one, relative to the root. Folders are included because
+/// `Docs/a` and `docs/b` clash too, once the folders merge
+#[must_use]
+pub fn tree_paths(root: &Path, scan: &FolderScan, side: usize) -> BTreeSet<TreePath> {
+    let mut paths = BTreeSet::new();
+    for file in scan.iter().chain(&scan.duplicates) {
+        let mut path = relative_path(root, &file.path.0);
+        while !path.as_os_str().is_empty() {
+            let parent = path.parent().map(Path::to_path_buf).unwrap_or_default();
+            paths.insert(TreePath { side, path });
+            path = parent;
+        }
+    }
+    paths
+}
+
+/// Groups of different paths that a case-insensitive, normalising filesystem would treat as the same.
+/// A clash between folders is reported once, not again for everything inside them
+#[must_use]
+pub fn find_collisions(paths: &BTreeSet<TreePath>) -> Vec<Vec<TreePath>> {
+    let mut groups: BTreeMap<PathBuf, BTreeMap<&Path, Vec<&TreePath>>> = BTreeMap::new();
+    for path in paths {
+        groups
+            .entry(FOLDED.apply_path(&path.path))
+            .or_default()
+            .entry(&path.path)
+            .or_default()
+            .push(path);
+    }
+
+    // parents sort before their children, so a clashing folder is always seen first
+    let mut clashing: Vec<&Path> = Vec::new();
+    let mut collisions = Vec::new();
+    for (folded, spellings) in &groups {
+        if spellings.len() < 2 || clashing.iter().any(|c| folded.starts_with(c)) {
+            continue;
+        }
+        clashing.push(folded);
+        collisions.push(spellings.values().flatten().map(|&p| p.clone()).collect());
+    }
+    collisions
+}
+
+/// Paths with a name that can't be created on Windows. Only the outermost bad name on a path is reported
+#[must_use]
+pub fn find_invalid(paths: &BTreeSet<TreePath>) -> Vec<InvalidName> {
+    let mut invalid: Vec<InvalidName> = Vec::new();
+    for path in paths {
+        let inside_invalid = invalid
+            .iter()
+            .any(|i| i.path.side == path.side && path.path.starts_with(&i.path.path));
+        if inside_invalid {
+            continue;
+        }
+
+        let name = path.path.file_name().unwrap_or_default();
+        if let Some(reason) = windows_name_problem(name) {
+            invalid.push(InvalidName {
+                path: path.clone(),
+                reason,
+            });
+        }
+    }
+    invalid
+}
+
+/// Why a name can't be used on Windows, if it can't
+#[must_use]
+pub fn windows_name_problem(name: &OsStr) -> Option<String> {
+    let Some(name) = name.to_str() else {
+        return Some("not valid Unicode".to_string());
+    };
+
+    if let Some(c) = name.chars().find(|c| RESERVED_CHARS.contains(c)) {
+        return Some(format!("contains '{c}'"));
+    }
+    if name.chars().any(char::is_control) {
+        return Some("contains a control character".to_string());
+    }
+    if name.ends_with('.') {
+        return Some("ends with a dot".to_string());
+    }
+    if name.ends_with(' ') {
+        return Some("ends with a space".to_string());
+    }
+
+    // CON and con.txt are both reserved
+    let stem = name.split('.').next().unwrap_or(name).trim_end();
+    RESERVED_NAMES
+        .iter()
+        .find(|reserved| stem.eq_ignore_ascii_case(reserved))
+        .map(|reserved| format!("reserved name {reserved}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn problem(name: &str) -> Option<String> {
+        windows_name_problem(OsStr::new(name))
+    }
+
+    #[test]
+    fn ordinary_names_are_fine() {
+        for name in [
+            "readme.md",
+            "Café.txt",
+            ".gitignore",
+            "CONFIG.sys",
+            "com10",
+            "a.b.c",
+        ] {
+            assert_eq!(problem(name), None, "{name}");
+        }
+    }
+
+    #[test]
+    fn reserved_characters() {
+        assert_eq!(problem("a:b"), Some("contains ':'".to_string()));
+        assert_eq!(problem("what?.txt"), Some("contains '?'".to_string()));
+        assert_eq!(problem("back\\slash"), Some("contains '\\'".to_string()));
+        assert_eq!(
+            problem("tab\there"),
+            Some("contains a control character".to_string())
+        );
+    }
+
+    #[test]
+    fn trailing_dot_or_space() {
+        assert_eq!(problem("name."), Some("ends with a dot".to_string()));
+        assert_eq!(problem("name "), Some("ends with a space".to_string()));
+    }
+
+    #[test]
+    fn reserved_names_with_any_case_or_extension() {
+        assert_eq!(problem("CON"), Some("reserved name CON".to_string()));
+        assert_eq!(problem("con.txt"), Some("reserved name CON".to_string()));
+        assert_eq!(
+            problem("Lpt9.tar.gz"),
+            Some("reserved name LPT9".to_string())
+        );
+        assert_eq!(problem("nul .txt"), Some("reserved name NUL".to_string()));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn names_that_are_not_unicode() {
+        use std::os::unix::ffi::OsStrExt;
+
+        assert_eq!(
+            windows_name_problem(OsStr::from_bytes(b"caf\xe9")),
+            Some("not valid Unicode".to_string())
+        );
+    }
+}
@@ -0,0 +1,148 @@
+use rayon::prelude::*;
+use std::path::Path;
+
+use crate::filedata::FilePath;
+use crate::progress::Progress;
+use crate::scan::{FolderScan, scan_folder};
+use crate::utils::Config;
+
+/// One key missing from at least one folder. `present` has an entry per folder, in command line order
+pub struct MatrixRow<'a> {
+    pub present: Vec<Option<&'a FilePath>>,
+}
+
+impl MatrixRow<'_> {
+    /// Number of folders that have this file
+    #[must_use]
+    pub fn count(&self) -> usize {
+        self.present.iter().flatten().count()
+    }
+
+    /// The file to show for this row, from the first folder that has it
+    /// # Panics
+    /// Never, a row is only made for a key found in at least one folder
+    #[must_use]
+    pub fn file(&self) -> &FilePath {
+        self.present
+            .iter()
+            .flatten()
+            .next()
+            .expect("every row is present somewhere")
+    }
+}
+
+/// Scan every folder, in parallel unless limited to one thread
+/// # Errors
+/// Will return an error if a folder cannot be scanned
+pub fn scan_all(
+    config: &Config,
+    roots: &[&Path],
+    progress: &Progress,
+) -> anyhow::Result<Vec<FolderScan>> {
+    if config.one_thread {
+        roots
+            .iter()
+            .enumerate()
+            .map(|(side, root)| scan_folder(config, root, progress, side))
+            .collect()
+    } else {
+        roots
+            .par_iter()
+            .enumerate()
+            .map(|(side, root)| scan_folder(config, root, progress, side))
+            .collect()
+    }
+}
+
+/// Find the files missing from at least one folder, most widely present first.
+/// Also returns how many files every folder has
+#[must_use]
+pub fn presence_matrix(scans: &[FolderScan]) -> (Vec<MatrixRow<'_>>, usize) {
+    let mut rows = Vec::new();
+    let mut everywhere = 0;
+
+    // each match gets one row, from the first folder that has it
+    for (side, scan) in scans.iter().enumerate() {
+        for file in scan.iter() {
+            if scans[..side].iter().any(|earlier| earlier.contains(file)) {
+                continue;
+            }
+
+            let present: Vec<Option<&FilePath>> = scans
+                .iter()
+                .map(|other| other.get(file).map(|matched| &matched.path))
+                .collect();
+            if present.iter().any(Option::is_none) {
+                rows.push(MatrixRow { present });
+            } else {
+                everywhere += 1;
+            }
+        }
+    }
+
+    rows.sort_by(|a, b| {
+        b.count()
+            .cmp(&a.count())
+            .then_with(|| a.file().0.cmp(&b.file().0))
+    });
+
+    (rows, everywhere)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::comparer::FileData;
+
+    fn scan(config: &Config, folder: &str, names: &[&str]) -> FolderScan {
+        let mut scan = FolderScan::new(config.file_comparer());
+        for name in names {
+            scan.insert(FileData {
+                path: FilePath(Path::new(folder).join(name)),
+                size: 1,
+                contents: None,
+            });
+        }
+        scan
+    }
+
+    #[test]
+    fn matrix_of_three_folders_with_partial_overlap() {
+        let config = Config::default();
+        let scans = [
+            scan(&config, "a", &["common.txt", "ab.txt", "a.txt"]),
+            scan(&config, "b", &["common.txt", "ab.txt", "bc.txt"]),
+            scan(&config, "c", &["common.txt", "bc.txt", "c.txt"]),
+        ];
+
+        let (rows, everywhere) = presence_matrix(&scans);
+        assert_eq!(everywhere, 1);
+
+        // most widely present first, then by the path shown, which comes from the first folder with the file
+        let summary: Vec<(String, String)> = rows
+            .iter()
+            .map(|row| {
+                let pattern = row
+                    .present
+                    .iter()
+                    .map(|file| if file.is_some() { 'X' } else { '-' })
+                    .collect();
+                (pattern, row.file().0.to_string_lossy().replace('\\', "/"))
+            })
+            .collect();
+        let expected = [
+            ("XX-", "a/ab.txt"),
+            ("-XX", "b/bc.txt"),
+            ("X--", "a/a.txt"),
+            ("--X", "c/c.txt"),
+        ];
+        assert_eq!(
+            summary,
+            expected.map(|(pattern, file)| (pattern.to_string(), file.to_string()))
+        );
+
+        // each folder's own copy is kept, not just the first
+        assert_eq!(rows[1].present[2].unwrap().0, Path::new("c/bc.txt"));
+        assert_eq!(rows[1].count(), 2);
+    }
+}
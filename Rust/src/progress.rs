@@ -0,0 +1,236 @@
+use std::fmt::Write as _;
+use std::io::{IsTerminal, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::utils::{folder_label, format_bytes};
+
+const REFRESH_INTERVAL: Duration = Duration::from_millis(250);
+const MAX_CURRENT_FILE_CHARS: usize = 50;
+const DEFAULT_TERMINAL_WIDTH: usize = 80;
+const MIN_CURRENT_FILE_CHARS: usize = 10; // any less of the path isn't worth showing
+
+/// Counters for one side of the comparison. Updated by the scanning threads with relaxed atomics,
+/// so the cost is negligible even when nothing is displayed
+#[derive(Default)]
+pub struct SideProgress {
+    pub files: AtomicU64,       // files scanned so far
+    pub bytes: AtomicU64,       // bytes read and hashed so far
+    pub total_bytes: AtomicU64, // total bytes to hash, known up front in Hash mode
+}
+
+impl SideProgress {
+    /// Record another file scanned
+    #[inline]
+    pub fn add_file(&self) {
+        self.files.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Shared progress state for a scan, and the renderer for the stderr status line
+pub struct Progress {
+    pub sides: Vec<SideProgress>, // one per folder, in command line order
+    current: Mutex<String>,       // most recent file started, on either side
+    show_eta: bool,               // only meaningful when file contents are fully hashed
+    finished: AtomicBool,
+    start: Instant,
+}
+
+impl Progress {
+    #[must_use]
+    pub fn new(show_eta: bool, folders: usize) -> Self {
+        Progress {
+            sides: (0..folders).map(|_| SideProgress::default()).collect(),
+            current: Mutex::new(String::new()),
+            show_eta,
+            finished: AtomicBool::new(false),
+            start: Instant::now(),
+        }
+    }
+
+    /// Progress is only shown on an interactive stderr, and never in raw mode
+    #[must_use]
+    pub fn should_display(raw: bool) -> bool {
+        !raw && std::io::stderr().is_terminal()
+    }
+
+    /// Record the file currently being processed
+    pub fn set_current(&self, path: &Path) {
+        if let Ok(mut current) = self.current.lock() {
+            current.clear();
+            current.push_str(&path.to_string_lossy());
+        }
+    }
+
+    /// Signal the display loop to stop
+    pub fn finish(&self) {
+        self.finished.store(true, Ordering::Relaxed);
+    }
+
+    /// Run `work` while the status line (if shown) is redrawn on its own thread
+    pub fn run_with_display<T>(&self, raw: bool, work: impl FnOnce() -> T) -> T {
+        std::thread::scope(|scope| {
+            let display = Self::should_display(raw).then(|| scope.spawn(|| self.display_loop()));
+
+            let result = work();
+
+            self.finish();
+            if let Some(display) = display {
+                display.thread().unpark();
+            }
+            result
+        })
+    }
+
+    /// Redraw the status line until `finish` is called, then clear it. Intended for its own thread
+    pub fn display_loop(&self) {
+        let mut stderr = std::io::stderr();
+        while !self.finished.load(Ordering::Relaxed) {
+            // errors writing to the terminal are not worth stopping the scan for
+            let _ = write!(stderr, "\r{}\x1b[K", self.status_line(terminal_width()));
+            let _ = stderr.flush();
+            std::thread::park_timeout(REFRESH_INTERVAL);
+        }
+        let _ = write!(stderr, "\r\x1b[K");
+        let _ = stderr.flush();
+    }
+
+    /// Build the one-line status text, no wider than the terminal
+    fn status_line(&self, width: usize) -> String {
+        let mut line = String::new();
+        let mut done = 0;
+        for (index, side) in self.sides.iter().enumerate() {
+            let files = side.files.load(Ordering::Relaxed);
+            let bytes = side.bytes.load(Ordering::Relaxed);
+            done += bytes;
+            let _ = write!(
+                line,
+                "{}: {files} files {} | ",
+                folder_label(index),
+                format_bytes(bytes)
+            );
+        }
+
+        let elapsed = self.start.elapsed().as_secs_f64();
+        #[allow(clippy::cast_precision_loss)]
+        let rate = if elapsed > 0.0 {
+            done as f64 / elapsed
+        } else {
+            0.0
+        };
+
+        line.push_str(&format_bytes_f64(rate));
+        line.push_str("/s");
+
+        if self.show_eta {
+            let total: u64 = self
+                .sides
+                .iter()
+                .map(|side| side.total_bytes.load(Ordering::Relaxed))
+                .sum();
+            if rate > 0.0 && total >= done {
+                #[allow(clippy::cast_precision_loss)]
+                let remaining = (total - done) as f64 / rate;
+                line.push_str(" | ETA ");
+                line.push_str(&format_duration(remaining));
+            }
+        }
+
+        let current = self
+            .current
+            .lock()
+            .map(|current| current.clone())
+            .unwrap_or_default();
+        fit_status(line, &current, width)
+    }
+}
+
+/// Columns on the terminal showing stderr, re-read on each redraw as it may be resized
+fn terminal_width() -> usize {
+    terminal_size::terminal_size_of(std::io::stderr())
+        .map_or(DEFAULT_TERMINAL_WIDTH, |(width, _)| usize::from(width.0))
+}
+
+/// Join the counters and the current file into a line that fits `width` columns. A line that wrapped would
+/// leave stale rows behind, as only the current row is cleared, so the path is shortened and then dropped, and
+/// the counters cut short only if they don't fit on their own. The last column is left free, as some terminals
+/// wrap as soon as it is written
+fn fit_status(mut counters: String, current: &str, width: usize) -> String {
+    let room = width.saturating_sub(1);
+    let used = counters.chars().count();
+
+    let path_room = room.saturating_sub(used + 3).min(MAX_CURRENT_FILE_CHARS);
+    if !current.is_empty() && path_room >= MIN_CURRENT_FILE_CHARS {
+        counters.push_str(" | ");
+        counters.push_str(&truncate_left(current, path_room));
+    } else if used > room {
+        counters = counters.chars().take(room).collect();
+    }
+    counters
+}
+
+/// Format a fractional byte count, for throughput
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn format_bytes_f64(bytes: f64) -> String {
+    format_bytes(bytes as u64)
+}
+
+/// Format seconds as h:mm:ss
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn format_duration(seconds: f64) -> String {
+    let seconds = seconds as u64;
+    format!(
+        "{}:{:02}:{:02}",
+        seconds / 3600,
+        (seconds / 60) % 60,
+        seconds % 60
+    )
+}
+
+/// Keep the last `max` characters of a string, so the file name end of a long path stays visible
+fn truncate_left(text: &str, max: usize) -> String {
+    let count = text.chars().count();
+    if count <= max {
+        text.to_string()
+    } else {
+        let tail: String = text.chars().skip(count - max + 1).collect();
+        format!("…{tail}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COUNTERS: &str = "A: 1234 files 5.6 GB | B: 1200 files 5.5 GB | 120.0 MB/s | ETA 0:01:02";
+
+    #[test]
+    fn status_fits_the_terminal() {
+        let path = "/home/user/photos/2019/summer/a very long folder name/IMG_0001.JPG";
+        for width in [20, 40, 80, 100, 200] {
+            let line = fit_status(COUNTERS.to_string(), path, width);
+            assert!(line.chars().count() < width, "{width}: {line}");
+        }
+    }
+
+    #[test]
+    fn path_is_shortened_then_dropped() {
+        let path = "/home/user/photos/2019/IMG_0001.JPG";
+
+        let wide = fit_status(COUNTERS.to_string(), path, 200);
+        assert_eq!(wide, format!("{COUNTERS} | {path}"));
+
+        let narrow = fit_status(COUNTERS.to_string(), path, 90);
+        assert!(narrow.starts_with(COUNTERS));
+        assert!(narrow.ends_with("IMG_0001.JPG"), "{narrow}");
+        assert!(narrow.contains('…'), "{narrow}");
+
+        assert_eq!(fit_status(COUNTERS.to_string(), path, 80), COUNTERS);
+        assert_eq!(
+            fit_status(COUNTERS.to_string(), path, 30),
+            COUNTERS.chars().take(29).collect::<String>()
+        );
+    }
+}
@@ -0,0 +1,478 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::path::{MAIN_SEPARATOR, Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::comparer::FileData;
+use crate::filedata::FilePath;
+use crate::meta::MetaMismatch;
+use crate::scan::ComparisonResult;
+use crate::summary::{self, DiffTree, Totals};
+use crate::utils::{Config, VERSION, format_bytes, format_count, format_time, relative_path};
+
+/// Styles for the report, inline so it works offline
+const STYLE: &str = "
+body { font-family: system-ui, sans-serif; margin: 2em; color: #222; }
+h1 { font-size: 1.5em; }
+h2 { font-size: 1.2em; margin-top: 2em; border-bottom: 1px solid #ccc; }
+table { border-collapse: collapse; margin: 0.5em 0; }
+th, td { padding: 0.2em 0.8em; text-align: left; border-bottom: 1px solid #eee; }
+td.number { text-align: right; }
+table.sortable th { cursor: pointer; background: #f4f4f4; }
+table.sortable th::after { content: ' \\2195'; color: #999; }
+details { margin-left: 1.2em; }
+summary { cursor: pointer; }
+ul { list-style: none; margin: 0; padding-left: 1.2em; }
+.missing { font-weight: bold; }
+.warning { background: #fff3cd; padding: 0.5em; border: 1px solid #e0c36c; }
+";
+
+/// Sorts a table by the clicked column, using each cell's `data-sort` value if it has one
+const SCRIPT: &str = "
+document.querySelectorAll('table.sortable th').forEach(function (th) {
+  th.addEventListener('click', function () {
+    var body = th.closest('table').tBodies[0];
+    var index = Array.prototype.indexOf.call(th.parentNode.children, th);
+    var ascending = th.dataset.order !== 'asc';
+    th.dataset.order = ascending ? 'asc' : 'desc';
+    var value = function (row) {
+      var cell = row.cells[index];
+      return cell.dataset.sort !== undefined ? cell.dataset.sort : cell.textContent;
+    };
+    var rows = Array.prototype.slice.call(body.rows);
+    rows.sort(function (x, y) {
+      var a = value(x), b = value(y);
+      var order = isNaN(a) || isNaN(b) ? a.localeCompare(b) : a - b;
+      return ascending ? order : -order;
+    });
+    rows.forEach(function (row) { body.appendChild(row); });
+  });
+});
+";
+
+/// The differences between two sides, sorted into the categories a report shows
+pub struct Report<'a> {
+    pub folders_only_a: Vec<&'a FilePath>, // folders only in A, outermost only
+    pub folders_only_b: Vec<&'a FilePath>,
+    pub only_a: Vec<&'a FileData>, // files only in A, other than changed ones
+    pub only_b: Vec<&'a FileData>,
+    pub changed: Vec<(&'a FileData, &'a FileData)>, // at the same relative path on both sides, but not matching
+    pub moved: Vec<(&'a FileData, &'a FileData)>,   // matching, but at different relative paths
+    pub metadata: &'a [MetaMismatch],               // from `--check-meta`, if it was given
+    pub incomplete: bool,
+}
+
+impl<'a> Report<'a> {
+    /// Sort the result of a comparison into categories. Paths are matched the way `--name-case` and
+    /// `--name-normalize` say
+    #[must_use]
+    pub fn new(
+        config: &Config,
+        result: &'a ComparisonResult,
+        metadata: &'a [MetaMismatch],
+    ) -> Self {
+        let form = config.name_form();
+        let key =
+            |root: &Path, file: &FileData| form.apply_path(&relative_path(root, &file.path.0));
+
+        // a file only in A with a file only in B at the same relative path has changed
+        let mut only_b: HashMap<PathBuf, &FileData> = result
+            .files_only_in_b()
+            .map(|file| (key(&config.folder2, file), file))
+            .collect();
+        let mut changed = Vec::new();
+        let mut only_a = Vec::new();
+        for file in result.files_only_in_a() {
+            match only_b.remove(&key(&config.folder1, file)) {
+                Some(other) => changed.push((file, other)),
+                None => only_a.push(file),
+            }
+        }
+        let mut only_b: Vec<&FileData> = only_b.into_values().collect();
+
+        let mut moved: Vec<(&FileData, &FileData)> = result
+            .matching_files()
+            .filter(|(a, b)| key(&config.folder1, a) != key(&config.folder2, b))
+            .collect();
+
+        let mut folders_only_a: Vec<&FilePath> = result.folders_only_in_a().collect();
+        let mut folders_only_b: Vec<&FilePath> = result.folders_only_in_b().collect();
+        folders_only_a.sort_by(|x, y| x.0.cmp(&y.0));
+        folders_only_b.sort_by(|x, y| x.0.cmp(&y.0));
+        only_a.sort_by(|x, y| x.path.0.cmp(&y.path.0));
+        only_b.sort_by(|x, y| x.path.0.cmp(&y.path.0));
+        changed.sort_by(|x, y| x.0.path.0.cmp(&y.0.path.0));
+        moved.sort_by(|x, y| x.0.path.0.cmp(&y.0.path.0));
+
+        Report {
+            folders_only_a,
+            folders_only_b,
+            only_a,
+            only_b,
+            changed,
+            moved,
+            metadata,
+            incomplete: result.is_incomplete(),
+        }
+    }
+
+    /// The report as a single HTML page, with its styles and script inline
+    #[must_use]
+    pub fn html(&self, config: &Config) -> String {
+        let (root1, root2) = (config.folder1.as_path(), config.folder2.as_path());
+        let changed_a: Vec<&FileData> = self.changed.iter().map(|(a, _)| *a).collect();
+        let moved_a: Vec<&FileData> = self.moved.iter().map(|(a, _)| *a).collect();
+
+        let mut html = String::new();
+        html.push_str("<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n");
+        html.push_str("<title>Folder comparison report</title>\n");
+        let _ = writeln!(html, "<style>{STYLE}</style>\n</head>\n<body>");
+        html.push_str("<h1>Folder comparison report</h1>\n");
+        if self.incomplete {
+            html.push_str(
+                "<p class=\"warning\">The scan was cancelled, so these differences are only partial.</p>\n",
+            );
+        }
+
+        self.write_summary(&mut html, config);
+
+        write_section(
+            &mut html,
+            &format!("Only in A ({})", escape(&root1.display().to_string())),
+            &DiffTree::new(root1, &self.folders_only_a, &self.only_a, config.expand),
+            &only_table(root1, &self.folders_only_a, &self.only_a, config.expand),
+        );
+        write_section(
+            &mut html,
+            &format!("Only in B ({})", escape(&root2.display().to_string())),
+            &DiffTree::new(root2, &self.folders_only_b, &self.only_b, config.expand),
+            &only_table(root2, &self.folders_only_b, &self.only_b, config.expand),
+        );
+        write_section(
+            &mut html,
+            "Changed, at the same path but not matching",
+            &DiffTree::new(root1, &[], &changed_a, false),
+            &changed_table(root1, &self.changed),
+        );
+        write_section(
+            &mut html,
+            "Moved, matching but at a different path (tree shows the path in A)",
+            &DiffTree::new(root1, &[], &moved_a, false),
+            &moved_table(root1, root2, &self.moved),
+        );
+        if !config.check_meta.is_empty() {
+            html.push_str("<h2>Metadata differences</h2>\n");
+            html.push_str(&metadata_table(self.metadata));
+        }
+
+        let _ = writeln!(html, "<script>{SCRIPT}</script>\n</body>\n</html>");
+        html
+    }
+
+    /// Roots, how files were compared, and the count and size of each category
+    fn write_summary(&self, html: &mut String, config: &Config) {
+        let _ = writeln!(
+            html,
+            "<table>\n<tr><th>Folder A</th><td>{}</td></tr>\n<tr><th>Folder B</th><td>{}</td></tr>",
+            escape(&config.folder1.display().to_string()),
+            escape(&config.folder2.display().to_string())
+        );
+        let _ = writeln!(
+            html,
+            "<tr><th>Comparison</th><td>{}</td></tr>\n<tr><th>Generated</th><td>{} UTC by folder_compare {}</td></tr>\n</table>",
+            config.comparison_label(),
+            format_time(SystemTime::now()),
+            VERSION.unwrap_or("?")
+        );
+
+        html.push_str(
+            "<table>\n<thead><tr><th>Category</th><th>Folders</th><th>Files</th><th>Bytes</th></tr></thead>\n<tbody>\n",
+        );
+        let rows = [
+            (
+                "Only in A",
+                Some(self.folders_only_a.len()),
+                Totals::of(self.only_a.iter().copied()),
+            ),
+            (
+                "Only in B",
+                Some(self.folders_only_b.len()),
+                Totals::of(self.only_b.iter().copied()),
+            ),
+            (
+                "Changed (size in A)",
+                None,
+                Totals::of(self.changed.iter().map(|(a, _)| *a)),
+            ),
+            (
+                "Moved",
+                None,
+                Totals::of(self.moved.iter().map(|(a, _)| *a)),
+            ),
+        ];
+        for (category, folders, totals) in rows {
+            let _ = writeln!(
+                html,
+                "<tr><td>{category}</td><td class=\"number\">{}</td><td class=\"number\">{}</td><td class=\"number\">{}</td></tr>",
+                folders.map(format_count).unwrap_or_default(),
+                format_count(totals.files),
+                format_bytes(totals.bytes)
+            );
+        }
+        if !config.check_meta.is_empty() {
+            let _ = writeln!(
+                html,
+                "<tr><td>Metadata differences</td><td></td><td class=\"number\">{}</td><td></td></tr>",
+                format_count(self.metadata.len())
+            );
+        }
+        html.push_str("</tbody>\n</table>\n");
+    }
+}
+
+/// Write the report of a comparison to a single HTML file, which can be viewed offline
+/// # Errors
+/// Will return an error if the file cannot be written
+pub fn write_report(
+    config: &Config,
+    result: &ComparisonResult,
+    metadata: &[MetaMismatch],
+    path: &Path,
+) -> anyhow::Result<()> {
+    let html = Report::new(config, result, metadata).html(config);
+    std::fs::write(path, html)
+        .map_err(|e| anyhow::anyhow!("Cannot write report '{}': {e}", path.display()))
+}
+
+/// A category: its heading, a collapsible tree and a sortable table
+fn write_section(html: &mut String, heading: &str, tree: &DiffTree, table: &str) {
+    let _ = writeln!(html, "<h2>{heading}</h2>");
+    if tree.children.is_empty() {
+        html.push_str("<p>None</p>\n");
+        return;
+    }
+    let _ = writeln!(
+        html,
+        "<details open>\n<summary>Tree ({})</summary>",
+        tree.totals
+    );
+    write_tree(html, tree);
+    html.push_str("</details>\n");
+    html.push_str(table);
+}
+
+/// Nested lists, with each folder collapsible
+fn write_tree(html: &mut String, tree: &DiffTree) {
+    html.push_str("<ul>\n");
+    for (name, child) in &tree.children {
+        let name = escape(&name.to_string_lossy());
+        if let Some(size) = child.file_size {
+            let _ = writeln!(html, "<li>{name} ({})</li>", format_bytes(size));
+            continue;
+        }
+
+        let (class, whole) = if child.missing {
+            (" class=\"missing\"", "whole folder, ")
+        } else {
+            ("", "")
+        };
+        let label = format!(
+            "<span{class}>{name}{MAIN_SEPARATOR}</span> ({whole}{})",
+            child.totals
+        );
+
+        // a folder only on this side has nothing below it unless expanded
+        if child.children.is_empty() {
+            let _ = writeln!(html, "<li>{label}</li>");
+            continue;
+        }
+        let _ = writeln!(html, "<li><details><summary>{label}</summary>");
+        write_tree(html, child);
+        html.push_str("</details></li>\n");
+    }
+    html.push_str("</ul>\n");
+}
+
+/// Folders and files only on one side. Files inside the folders are only listed if `expand` is set
+fn only_table(root: &Path, folders: &[&FilePath], files: &[&FileData], expand: bool) -> String {
+    let mut table = table_head(&["Path", "Files", "Size"]);
+    let totals = summary::folder_totals(folders, files.iter().copied());
+    for (folder, totals) in folders.iter().zip(totals) {
+        let _ = writeln!(
+            table,
+            "<tr><td>{}{MAIN_SEPARATOR}</td>{}{}</tr>",
+            relative(root, &folder.0),
+            number_cell(totals.files as u64, &format_count(totals.files)),
+            size_cell(totals.bytes)
+        );
+    }
+    for file in files {
+        if !expand
+            && folders
+                .iter()
+                .any(|folder| file.path.0.starts_with(&folder.0))
+        {
+            continue;
+        }
+        let _ = writeln!(
+            table,
+            "<tr><td>{}</td>{}{}</tr>",
+            relative(root, &file.path.0),
+            number_cell(1, "1"),
+            size_cell(file.size)
+        );
+    }
+    table.push_str("</tbody>\n</table>\n");
+    table
+}
+
+/// Files at the same relative path that don't match, with both sizes
+fn changed_table(root: &Path, changed: &[(&FileData, &FileData)]) -> String {
+    let mut table = table_head(&["Path", "Size in A", "Size in B"]);
+    for (a, b) in changed {
+        let _ = writeln!(
+            table,
+            "<tr><td>{}</td>{}{}</tr>",
+            relative(root, &a.path.0),
+            size_cell(a.size),
+            size_cell(b.size)
+        );
+    }
+    table.push_str("</tbody>\n</table>\n");
+    table
+}
+
+/// Matching files at different relative paths
+fn moved_table(root1: &Path, root2: &Path, moved: &[(&FileData, &FileData)]) -> String {
+    let mut table = table_head(&["Path in A", "Path in B", "Size"]);
+    for (a, b) in moved {
+        let _ = writeln!(
+            table,
+            "<tr><td>{}</td><td>{}</td>{}</tr>",
+            relative(root1, &a.path.0),
+            relative(root2, &b.path.0),
+            size_cell(a.size)
+        );
+    }
+    table.push_str("</tbody>\n</table>\n");
+    table
+}
+
+/// Each metadata difference, with the value in A then the value in B
+fn metadata_table(metadata: &[MetaMismatch]) -> String {
+    if metadata.is_empty() {
+        return "<p>None</p>\n".to_string();
+    }
+    let mut table = table_head(&["Path", "Field", "A", "B"]);
+    for m in metadata {
+        let _ = writeln!(
+            table,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            escape(&m.path.display().to_string()),
+            escape(&m.field),
+            escape(&m.a),
+            escape(&m.b)
+        );
+    }
+    table.push_str("</tbody>\n</table>\n");
+    table
+}
+
+/// The start of a sortable table, up to the opening of its body
+fn table_head(columns: &[&str]) -> String {
+    let headings = columns.iter().fold(String::new(), |mut headings, c| {
+        let _ = write!(headings, "<th>{c}</th>");
+        headings
+    });
+    format!("<table class=\"sortable\">\n<thead><tr>{headings}</tr></thead>\n<tbody>\n")
+}
+
+/// A number, sorted by its value rather than its text
+fn number_cell(value: u64, text: &str) -> String {
+    format!("<td class=\"number\" data-sort=\"{value}\">{text}</td>")
+}
+
+/// A size for humans, sorted by bytes
+fn size_cell(bytes: u64) -> String {
+    number_cell(bytes, &format_bytes(bytes))
+}
+
+/// A path relative to its root, escaped for HTML
+fn relative(root: &Path, path: &Path) -> String {
+    escape(&relative_path(root, path).display().to_string())
+}
+
+/// Escape text for HTML content and attributes
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NAME: &str = "<script>&\"'.txt";
+    const ESCAPED: &str = "&lt;script&gt;&amp;&quot;&#39;.txt";
+
+    fn file(path: PathBuf, size: u64) -> FileData {
+        FileData {
+            path: FilePath(path),
+            size,
+            contents: None,
+        }
+    }
+
+    #[test]
+    fn file_names_are_escaped() {
+        let config = Config {
+            folder1: PathBuf::from("/a<b>"),
+            folder2: PathBuf::from("/b"),
+            ..Config::default()
+        };
+        let folder = FilePath(config.folder1.join(NAME));
+        let only = file(config.folder1.join(NAME).join(NAME), 1);
+        let changed = (
+            file(config.folder1.join(NAME), 2),
+            file(config.folder2.join(NAME), 3),
+        );
+        let moved = (
+            file(config.folder1.join("x").join(NAME), 4),
+            file(config.folder2.join(NAME), 4),
+        );
+        let report = Report {
+            folders_only_a: vec![&folder],
+            folders_only_b: Vec::new(),
+            only_a: vec![&only],
+            only_b: Vec::new(),
+            changed: vec![(&changed.0, &changed.1)],
+            moved: vec![(&moved.0, &moved.1)],
+            metadata: &[],
+            incomplete: false,
+        };
+
+        let html = report.html(&config);
+        assert!(!html.contains(NAME));
+        assert!(!html.contains("<script>&"));
+        assert!(html.contains("/a&lt;b&gt;"));
+        // the tree and the tables of the folder only in A, the changed file and both paths of the moved one
+        assert!(html.matches(ESCAPED).count() >= 5);
+        assert_eq!(html.matches("<script>").count(), 1);
+    }
+
+    #[test]
+    fn escape_every_special_character() {
+        assert_eq!(escape(NAME), ESCAPED);
+        assert_eq!(escape("plain name.txt"), "plain name.txt");
+    }
+}
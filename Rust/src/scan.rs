@@ -0,0 +1,910 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use walkdir::{DirEntry, WalkDir};
+
+use crate::archive::{self, ArchiveKind};
+use crate::cancel::{self, CancelToken};
+use crate::comparer::{Comparer, FileComparer, FileData, NameForm};
+use crate::error::Error;
+use crate::events::{Event, EventKind, EventSink};
+use crate::filedata::{FileDataCompareOption, FilePath, HashAlgorithm, IoStrategy};
+use crate::filelist;
+use crate::git::{self, GitSource};
+use crate::progress::{Progress, SideProgress};
+use crate::utils::{Config, hash_file, hash_file_git_blob, hash_file_quick, relative_path};
+
+/// Builds a comparison of two folders, archives or git revisions. Anything not set keeps the command line default
+///
+/// ```no_run
+/// use folder_compare::{FileDataCompareOption, HashAlgorithm, ScanBuilder};
+///
+/// let result = ScanBuilder::new("release", "staging")
+///     .comparison(FileDataCompareOption::Hash)
+///     .algorithm(HashAlgorithm::Blake3)
+///     .scan()?;
+/// for file in result.only_in_a() {
+///     println!("missing from staging: {file}");
+/// }
+/// # Ok::<(), folder_compare::Error>(())
+/// ```
+pub struct ScanBuilder {
+    config: Config,
+}
+
+impl ScanBuilder {
+    /// Compare folder A with folder B
+    pub fn new(folder_a: impl Into<PathBuf>, folder_b: impl Into<PathBuf>) -> Self {
+        ScanBuilder {
+            config: Config {
+                folder1: folder_a.into(),
+                folder2: folder_b.into(),
+                ..Config::default()
+            },
+        }
+    }
+
+    /// Match files with a custom comparer, such as one ignoring case. File contents are only hashed for it if
+    /// the comparison option reads them
+    #[must_use]
+    pub fn comparer(mut self, comparer: impl Comparer<FileData> + 'static) -> Self {
+        self.config.custom_comparer = Some(Arc::new(comparer));
+        self
+    }
+
+    /// Send events to a callback or channel as the scan runs
+    #[must_use]
+    pub fn events(mut self, sink: impl EventSink + 'static) -> Self {
+        self.config.events = Some(Arc::new(sink));
+        self
+    }
+
+    /// A handle that cancels this scan, from another thread or a signal handler. The scan then returns what it
+    /// found so far, marked incomplete. Other scans are unaffected
+    #[must_use]
+    pub fn cancel_token(&self) -> CancelToken {
+        self.config.cancel.clone()
+    }
+
+    /// Start from a complete configuration, such as one parsed from the command line
+    #[must_use]
+    pub fn from_config(config: Config) -> Self {
+        ScanBuilder { config }
+    }
+
+    /// How files are keyed and matched. Default is Name
+    #[must_use]
+    pub fn comparison(mut self, comparer: FileDataCompareOption) -> Self {
+        self.config.comparer = comparer;
+        self
+    }
+
+    /// Hash algorithm for keys and file contents. Default is SHA256
+    #[must_use]
+    pub fn algorithm(mut self, algorithm: HashAlgorithm) -> Self {
+        self.config.algorithm = algorithm;
+        self
+    }
+
+    /// How file contents are read when hashing. Default is Auto
+    #[must_use]
+    pub fn io(mut self, io: IoStrategy) -> Self {
+        self.config.io = io;
+        self
+    }
+
+    /// Bytes read from each end of a file for `QuickHash`. Default is 64 KiB
+    #[must_use]
+    pub fn quick_bytes(mut self, quick_bytes: u64) -> Self {
+        self.config.quick_bytes = quick_bytes;
+        self
+    }
+
+    /// Fully hash files that match on `QuickHash`, reporting any that differ
+    #[must_use]
+    pub fn verify(mut self, verify: bool) -> Self {
+        self.config.verify = verify;
+        self
+    }
+
+    /// Scan the folders one after the other, rather than in parallel
+    #[must_use]
+    pub fn one_thread(mut self, one_thread: bool) -> Self {
+        self.config.one_thread = one_thread;
+        self
+    }
+
+    /// Compare the members of archives found in the folders, rather than the archives
+    #[must_use]
+    pub fn descend_archives(mut self, descend_archives: bool) -> Self {
+        self.config.descend_archives = descend_archives;
+        self
+    }
+
+    /// Compare the files in a list instead of walking folder A
+    #[must_use]
+    pub fn list_a(mut self, list: impl Into<PathBuf>) -> Self {
+        self.config.list_a = Some(list.into());
+        self
+    }
+
+    /// Compare the files in a list instead of walking folder B
+    #[must_use]
+    pub fn list_b(mut self, list: impl Into<PathBuf>) -> Self {
+        self.config.list_b = Some(list.into());
+        self
+    }
+
+    /// The configuration built so far
+    #[must_use]
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Scan both sides and compare them
+    /// # Errors
+    /// Will return an error if either side cannot be scanned
+    pub fn scan(&self) -> Result<ComparisonResult, Error> {
+        let progress = Progress::new(self.config.show_eta(), 2);
+        compare(&self.config, &progress)
+    }
+
+    /// Scan both sides and compare them, updating the given progress counters as it goes
+    /// # Errors
+    /// Will return an error if either side cannot be scanned
+    pub fn scan_with_progress(&self, progress: &Progress) -> Result<ComparisonResult, Error> {
+        compare(&self.config, progress)
+    }
+}
+
+/// Scan both sides of a configuration and compare them
+/// # Errors
+/// Will return an error if either side cannot be scanned
+pub fn compare(config: &Config, progress: &Progress) -> Result<ComparisonResult, Error> {
+    let scan = scan_both(config, progress)?;
+
+    // folders are matched by relative path, whatever the comparison. One only stands for its files if none of
+    // them matched, since comparisons by name or contents match files wherever they are
+    let form = config.name_form();
+    let folders1 = folder_paths(&config.folder1, &scan.scan1, form);
+    let folders2 = folder_paths(&config.folder2, &scan.scan2, form);
+    let matched1 = folders_with_matches(
+        &config.folder1,
+        &scan.scan1,
+        &scan.scan2,
+        &scan.mismatch1,
+        form,
+    );
+    let matched2 = folders_with_matches(
+        &config.folder2,
+        &scan.scan2,
+        &scan.scan1,
+        &scan.mismatch2,
+        form,
+    );
+    let result = ComparisonResult {
+        folders_only1: folders_only_in(&folders1, &folders2, &matched1),
+        folders_only2: folders_only_in(&folders2, &folders1, &matched2),
+        scan,
+        incomplete: config.cancel.is_cancelled(),
+    };
+
+    // the differences are only walked again for a sink that wants each one
+    if config.wants_event(EventKind::DifferenceFound) {
+        let differences = result
+            .only_in_a()
+            .map(|path| (0, path))
+            .chain(result.only_in_b().map(|path| (1, path)));
+        for (side, path) in differences {
+            config.emit(EventKind::DifferenceFound, || Event::DifferenceFound {
+                side,
+                path: path.clone(),
+            });
+        }
+    }
+    config.emit(EventKind::Finished, || Event::Finished {
+        differences: result.difference_count(),
+        incomplete: result.incomplete,
+    });
+
+    Ok(result)
+}
+
+/// The outcome of comparing two sides. `QuickHash` matches that failed verification count as differences on both sides
+pub struct ComparisonResult {
+    pub scan: ScanOutput,
+    folders_only1: Vec<FilePath>,
+    folders_only2: Vec<FilePath>,
+    incomplete: bool,
+}
+
+impl ComparisonResult {
+    /// Files in A with no match in B
+    pub fn only_in_a(&self) -> impl Iterator<Item = &FilePath> {
+        self.files_only_in_a().map(|file| &file.path)
+    }
+
+    /// Files in B with no match in A
+    pub fn only_in_b(&self) -> impl Iterator<Item = &FilePath> {
+        self.files_only_in_b().map(|file| &file.path)
+    }
+
+    /// Files in A with no match in B, with their sizes
+    pub fn files_only_in_a(&self) -> impl Iterator<Item = &FileData> {
+        self.scan
+            .scan1
+            .difference(&self.scan.scan2)
+            .chain(&self.scan.mismatch1)
+    }
+
+    /// Files in B with no match in A, with their sizes
+    pub fn files_only_in_b(&self) -> impl Iterator<Item = &FileData> {
+        self.scan
+            .scan2
+            .difference(&self.scan.scan1)
+            .chain(&self.scan.mismatch2)
+    }
+
+    /// Every file in A with no match in B, including those kept as duplicates of another file in A. Copying and
+    /// deleting need these, as each duplicate is a file of its own
+    pub fn all_only_in_a(&self) -> impl Iterator<Item = &FilePath> {
+        all_unmatched(&self.scan.scan1, &self.scan.scan2, &self.scan.mismatch1)
+    }
+
+    /// Every file in B with no match in A, including those kept as duplicates of another file in B
+    pub fn all_only_in_b(&self) -> impl Iterator<Item = &FilePath> {
+        all_unmatched(&self.scan.scan2, &self.scan.scan1, &self.scan.mismatch2)
+    }
+
+    /// Folders in A with no folder at the same relative path in B, including empty ones, and holding no file that
+    /// matched one in B. Only the outermost is listed, as it stands for everything inside it
+    pub fn folders_only_in_a(&self) -> impl Iterator<Item = &FilePath> {
+        self.folders_only1.iter()
+    }
+
+    /// Folders in B with no folder at the same relative path in A
+    pub fn folders_only_in_b(&self) -> impl Iterator<Item = &FilePath> {
+        self.folders_only2.iter()
+    }
+
+    /// Files in A with no match in B, leaving out those inside a folder only in A
+    pub fn only_in_a_collapsed(&self) -> impl Iterator<Item = &FilePath> {
+        self.only_in_a()
+            .filter(|file| !inside_any(file, &self.folders_only1))
+    }
+
+    /// Files in B with no match in A, leaving out those inside a folder only in B
+    pub fn only_in_b_collapsed(&self) -> impl Iterator<Item = &FilePath> {
+        self.only_in_b()
+            .filter(|file| !inside_any(file, &self.folders_only2))
+    }
+
+    /// Pairs of matching files, A then B. Only one file per key is paired, see `FolderScan`
+    pub fn matches(&self) -> impl Iterator<Item = (&FilePath, &FilePath)> {
+        self.matching_files().map(|(a, b)| (&a.path, &b.path))
+    }
+
+    /// Pairs of matching files, A then B, with their sizes
+    pub fn matching_files(&self) -> impl Iterator<Item = (&FileData, &FileData)> {
+        let mismatched: HashSet<&Path> = self
+            .scan
+            .mismatch1
+            .iter()
+            .map(|f| f.path.0.as_path())
+            .collect();
+        self.scan
+            .scan1
+            .intersection(&self.scan.scan2)
+            .filter(move |(a, _)| !mismatched.contains(a.path.0.as_path()))
+    }
+
+    /// Number of files on either side with no match on the other
+    #[must_use]
+    pub fn difference_count(&self) -> usize {
+        self.only_in_a().count() + self.only_in_b().count()
+    }
+
+    /// True if the scan was cancelled, so the differences are only partial
+    #[must_use]
+    pub fn is_incomplete(&self) -> bool {
+        self.incomplete
+    }
+}
+
+/// Files on one side, duplicates included, with no match on the other, then those that failed verification
+fn all_unmatched<'a>(
+    scan: &'a FolderScan,
+    other: &'a FolderScan,
+    mismatched: &'a [FileData],
+) -> impl Iterator<Item = &'a FilePath> {
+    scan.iter()
+        .chain(&scan.duplicates)
+        .filter(|file| !other.contains(file))
+        .chain(mismatched)
+        .map(|file| &file.path)
+}
+
+/// Is the file inside one of the folders?
+fn inside_any(file: &FilePath, folders: &[FilePath]) -> bool {
+    folders.iter().any(|folder| file.0.starts_with(&folder.0))
+}
+
+/// Every folder on one side by folded relative path, with the path as shown. Folders holding files are implied
+/// by the files, which covers archives, git revisions and file lists. A walk also finds the empty ones
+fn folder_paths(root: &Path, scan: &FolderScan, form: NameForm) -> BTreeMap<PathBuf, FilePath> {
+    let mut folders = BTreeMap::new();
+    for folder in &scan.folders {
+        let relative = relative_path(root, &folder.0);
+        folders.insert(form.apply_path(&relative), folder.clone());
+    }
+
+    for file in scan.iter().chain(&scan.duplicates) {
+        let relative = relative_path(root, &file.path.0);
+        if relative.is_absolute() {
+            continue;
+        }
+
+        // walk up the shown path and the relative path together, stopping at the root
+        let (mut shown, mut relative) = (file.path.0.as_path(), relative.as_path());
+        while let (Some(shown_parent), Some(parent)) = (shown.parent(), relative.parent()) {
+            if parent.as_os_str().is_empty() {
+                break;
+            }
+            folders
+                .entry(form.apply_path(parent))
+                .or_insert_with(|| FilePath(shown_parent.to_path_buf()));
+            (shown, relative) = (shown_parent, parent);
+        }
+    }
+    folders
+}
+
+/// Folders holding a file, at any depth, that matched a file on the other side, by folded relative path.
+/// `QuickHash` matches that failed verification don't count
+fn folders_with_matches(
+    root: &Path,
+    scan: &FolderScan,
+    other: &FolderScan,
+    mismatched: &[FileData],
+    form: NameForm,
+) -> HashSet<PathBuf> {
+    let mismatched: HashSet<&Path> = mismatched.iter().map(|f| f.path.0.as_path()).collect();
+    let mut matched = HashSet::new();
+    for file in scan.iter().chain(&scan.duplicates) {
+        if !other.contains(file) || mismatched.contains(file.path.0.as_path()) {
+            continue;
+        }
+        let relative = relative_path(root, &file.path.0);
+        if relative.is_absolute() {
+            continue;
+        }
+
+        // once a folder is marked, so are all the folders above it
+        for parent in relative.ancestors().skip(1) {
+            if parent.as_os_str().is_empty() || !matched.insert(form.apply_path(parent)) {
+                break;
+            }
+        }
+    }
+    matched
+}
+
+/// Folders with no folder at the same relative path on the other side and no matched file inside, outermost only
+fn folders_only_in(
+    folders: &BTreeMap<PathBuf, FilePath>,
+    other: &BTreeMap<PathBuf, FilePath>,
+    matched: &HashSet<PathBuf>,
+) -> Vec<FilePath> {
+    // a folder's contents sort straight after it, so only the last folder found can contain this one
+    let mut only: Vec<(&PathBuf, &FilePath)> = Vec::new();
+    for (relative, folder) in folders {
+        let inside_last = only
+            .last()
+            .is_some_and(|(last, _)| relative.starts_with(last));
+        if !inside_last && !other.contains_key(relative) && !matched.contains(relative) {
+            only.push((relative, folder));
+        }
+    }
+    only.into_iter().map(|(_, folder)| folder.clone()).collect()
+}
+
+/// The files found in one folder, matched by a comparer. Files are kept in buckets by the comparer's hash, and
+/// only one file is kept per match, so any others are listed as duplicates
+pub struct FolderScan {
+    comparer: FileComparer,
+    buckets: HashMap<usize, Vec<FileData>>,
+    pub duplicates: Vec<FileData>,
+    pub folders: Vec<FilePath>, // folders found by walking, including empty ones
+}
+
+impl FolderScan {
+    /// An empty scan, matching files with the given comparer
+    #[must_use]
+    pub fn new(comparer: FileComparer) -> Self {
+        FolderScan {
+            comparer,
+            buckets: HashMap::new(),
+            duplicates: Vec::new(),
+            folders: Vec::new(),
+        }
+    }
+
+    /// Add a file. Any equal file it displaces is recorded as a duplicate
+    pub fn insert(&mut self, file: FileData) {
+        let bucket = self.buckets.entry(self.comparer.hash(&file)).or_default();
+        match bucket.iter().position(|x| self.comparer.equals(x, &file)) {
+            Some(pos) => {
+                let previous = std::mem::replace(&mut bucket[pos], file);
+                self.duplicates.push(previous);
+            }
+            None => bucket.push(file),
+        }
+    }
+
+    /// Add every file from another scan, such as the members of an archive
+    pub fn extend(&mut self, other: FolderScan) {
+        for file in other.buckets.into_values().flatten() {
+            self.insert(file);
+        }
+        self.duplicates.extend(other.duplicates);
+        self.folders.extend(other.folders);
+    }
+
+    /// The file in this scan equal to `file`, if any
+    #[must_use]
+    pub fn get(&self, file: &FileData) -> Option<&FileData> {
+        self.buckets
+            .get(&self.comparer.hash(file))?
+            .iter()
+            .find(|x| self.comparer.equals(x, file))
+    }
+
+    /// Is there a file in this scan equal to `file`?
+    #[must_use]
+    pub fn contains(&self, file: &FileData) -> bool {
+        self.get(file).is_some()
+    }
+
+    /// Iterate over the files, one per match
+    pub fn iter(&self) -> impl Iterator<Item = &FileData> {
+        self.buckets.values().flatten()
+    }
+
+    /// Number of files, not counting duplicates
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.buckets.values().map(Vec::len).sum()
+    }
+
+    /// Is the scan empty?
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.buckets.is_empty()
+    }
+
+    /// Files in this scan with no match in `other`
+    pub fn difference<'a>(&'a self, other: &'a FolderScan) -> impl Iterator<Item = &'a FileData> {
+        self.iter().filter(move |file| !other.contains(file))
+    }
+
+    /// Pairs of matching files, this scan's first
+    pub fn intersection<'a>(
+        &'a self,
+        other: &'a FolderScan,
+    ) -> impl Iterator<Item = (&'a FileData, &'a FileData)> {
+        self.iter()
+            .filter_map(move |file| other.get(file).map(|matched| (file, matched)))
+    }
+
+    /// Get the largest current bucket size, a measure of how well the comparer hashes
+    #[must_use]
+    pub fn largest_bucket_size(&self) -> usize {
+        self.buckets.values().map(Vec::len).max().unwrap_or(0)
+    }
+}
+
+/// The scanned files for each folder, and the `QuickHash` matches that failed verification
+pub struct ScanOutput {
+    pub scan1: FolderScan,
+    pub scan2: FolderScan,
+    pub mismatch1: Vec<FileData>,
+    pub mismatch2: Vec<FileData>,
+}
+
+/// Scan both folders, then verify `QuickHash` matches if required
+/// # Errors
+/// Will return an error if either folder cannot be scanned
+pub fn scan_both(config: &Config, progress: &Progress) -> anyhow::Result<ScanOutput> {
+    // create the hashsets
+    let scan1;
+    let scan2;
+
+    // scan the folders and populate the HashSets
+    if config.one_thread {
+        // scan the two folders in series, using one thread
+        scan1 = scan_folder(config, &config.folder1, progress, 0)?;
+        scan2 = scan_folder(config, &config.folder2, progress, 1)?;
+    } else {
+        // scan them in parallel
+        let (res_files_1, res_files_2) = rayon::join(
+            || scan_folder(config, &config.folder1, progress, 0),
+            || scan_folder(config, &config.folder2, progress, 1),
+        );
+
+        scan1 = res_files_1?;
+        scan2 = res_files_2?;
+    }
+
+    // QuickHash matches are only probable, so optionally confirm them with a full hash
+    let (mismatch1, mismatch2) = if config.verify {
+        verify_matches(config, &scan1, &scan2, progress)?
+    } else {
+        (Vec::new(), Vec::new())
+    };
+
+    Ok(ScanOutput {
+        scan1,
+        scan2,
+        mismatch1,
+        mismatch2,
+    })
+}
+
+/// Fully hash each pair of files that matched on key. Returns the pairs whose contents actually differ,
+/// split into the folder1 and folder2 sides
+fn verify_matches(
+    config: &Config,
+    scan1: &FolderScan,
+    scan2: &FolderScan,
+    progress: &Progress,
+) -> anyhow::Result<(Vec<FileData>, Vec<FileData>)> {
+    let (progress1, progress2) = (&progress.sides[0], &progress.sides[1]);
+
+    let mut mismatch1 = Vec::new();
+    let mut mismatch2 = Vec::new();
+
+    for (f1, f2) in scan1.intersection(scan2) {
+        if config.cancel.is_cancelled() {
+            break;
+        }
+
+        let (hash1, hash2) = if config.one_thread {
+            (
+                hash_file(
+                    &f1.path.0,
+                    config.algorithm,
+                    config.io,
+                    Some(&progress1.bytes),
+                    &config.cancel,
+                ),
+                hash_file(
+                    &f2.path.0,
+                    config.algorithm,
+                    config.io,
+                    Some(&progress2.bytes),
+                    &config.cancel,
+                ),
+            )
+        } else {
+            rayon::join(
+                || {
+                    hash_file(
+                        &f1.path.0,
+                        config.algorithm,
+                        config.io,
+                        Some(&progress1.bytes),
+                        &config.cancel,
+                    )
+                },
+                || {
+                    hash_file(
+                        &f2.path.0,
+                        config.algorithm,
+                        config.io,
+                        Some(&progress2.bytes),
+                        &config.cancel,
+                    )
+                },
+            )
+        };
+
+        let (hash1, hash2) = match (hash1, hash2) {
+            (Ok(hash1), Ok(hash2)) => (hash1, hash2),
+            (Err(e), _) | (_, Err(e)) if cancel::is_cancellation(&e) => break,
+            (Err(e), _) | (_, Err(e)) => return Err(e),
+        };
+
+        if hash1 != hash2 {
+            mismatch1.push(f1.clone());
+            mismatch2.push(f2.clone());
+        }
+    }
+
+    Ok((mismatch1, mismatch2))
+}
+
+/// Scan a folder and build hashset with the files. `side` selects the progress counters, 0 for A and 1 for B.
+/// The folder can also be an archive or git revision
+/// # Errors
+/// Will return an error if the folder cannot be scanned
+pub fn scan_folder(
+    config: &Config,
+    dir: &Path,
+    progress: &Progress,
+    side: usize,
+) -> anyhow::Result<FolderScan> {
+    if let Some(source) = GitSource::from_root(dir) {
+        return git::scan_git(config, &source, dir, progress, side);
+    }
+    if let Some(kind) = ArchiveKind::from_path(dir) {
+        return archive::scan_archive(config, dir, kind, progress, side);
+    }
+    if !dir.is_dir() {
+        return Err(anyhow::anyhow!(
+            "'{}' is not a folder or a supported archive",
+            dir.display()
+        ));
+    }
+
+    let mut scan = FolderScan::new(config.file_comparer());
+    let counters = &progress.sides[side];
+
+    // a list of files replaces the walk, if one was given for this side
+    let walker: Box<dyn Iterator<Item = DirEntry>> = match config.file_list(side) {
+        Some(list) => Box::new(filelist::read_list(config, side, list, dir)?.into_iter()),
+        None => Box::new(
+            WalkDir::new(dir)
+                .into_iter()
+                .filter_map(move |entry| match entry {
+                    Ok(entry) => Some(entry),
+                    Err(e) => {
+                        config.emit(EventKind::WalkError, || Event::WalkError {
+                            side,
+                            path: e.path().map(Path::to_path_buf),
+                            message: walk_error_message(&e),
+                        });
+                        None
+                    }
+                })
+                // the root itself is not compared
+                .filter(|entry| entry.file_type().is_file() || entry.depth() > 0),
+        ),
+    };
+
+    // when fully hashing, enumerate everything first so the total bytes are known for the ETA
+    let entries: Box<dyn Iterator<Item = DirEntry>> = if config.show_eta() {
+        let entries: Vec<DirEntry> = walker.collect();
+        let total: u64 = entries
+            .iter()
+            .filter(|entry| entry.file_type().is_file())
+            .filter_map(|entry| entry.metadata().ok())
+            .map(|metadata| metadata.len())
+            .sum();
+        counters.total_bytes.fetch_add(total, Ordering::Relaxed);
+        Box::new(entries.into_iter())
+    } else {
+        Box::new(walker)
+    };
+
+    for entry in entries {
+        if config.cancel.is_cancelled() {
+            break;
+        }
+
+        let file_path = entry.path();
+        if entry.file_type().is_dir() {
+            scan.folders.push(file_path.into());
+            continue;
+        }
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        progress.set_current(file_path);
+
+        // an archive in the tree is replaced by its members. One that can't be read is compared as a file
+        if config.descend_archives
+            && let Some(kind) = ArchiveKind::from_name(entry.file_name())
+        {
+            match archive::scan_archive(config, file_path, kind, progress, side) {
+                Ok(members) => {
+                    scan.extend(members);
+                    continue;
+                }
+                Err(e) => {
+                    config.warn(
+                        Some(side),
+                        file_path,
+                        format!("{e:#}, comparing it as a file"),
+                    );
+                }
+            }
+        }
+
+        // hash the contents if the comparison needs them. Cancellation keeps what was scanned so far
+        let file = match file_data(config, &entry, counters) {
+            Ok(file) => file,
+            Err(e) if cancel::is_cancellation(&e) => break,
+            Err(e) => return Err(e),
+        };
+
+        // insert the file into the scan, where the comparer places it
+        add_scanned(config, &mut scan, file, side, counters);
+    }
+
+    Ok(scan)
+}
+
+/// Why a walk couldn't read a path, without the path itself, which the event carries
+#[must_use]
+pub(crate) fn walk_error_message(error: &walkdir::Error) -> String {
+    error
+        .io_error()
+        .map_or_else(|| error.to_string(), ToString::to_string)
+}
+
+/// Insert a scanned file, count it and report it to any event sink
+pub(crate) fn add_scanned(
+    config: &Config,
+    scan: &mut FolderScan,
+    file: FileData,
+    side: usize,
+    counters: &SideProgress,
+) {
+    if let Some(hash) = &file.contents {
+        config.emit(EventKind::FileHashed, || Event::FileHashed {
+            side,
+            path: file.path.clone(),
+            hash: hash.clone(),
+        });
+    }
+    config.emit(EventKind::FileScanned, || Event::FileScanned {
+        side,
+        path: file.path.clone(),
+        size: file.size,
+    });
+
+    scan.insert(file);
+    counters.add_file();
+}
+
+/// Gather what a comparer needs to know about a file, hashing the contents if the comparison option reads them
+/// # Errors
+/// Will return an error if the file cannot be read
+pub fn file_data(
+    config: &Config,
+    entry: &DirEntry,
+    counters: &SideProgress,
+) -> anyhow::Result<FileData> {
+    let file_path = entry.path();
+    let size = entry.metadata()?.len();
+
+    let contents = match config.comparer {
+        FileDataCompareOption::Name
+        | FileDataCompareOption::NameSize
+        | FileDataCompareOption::Stem => None,
+        FileDataCompareOption::Hash => Some(hash_file(
+            file_path,
+            config.algorithm,
+            config.io,
+            Some(&counters.bytes),
+            &config.cancel,
+        )?),
+        FileDataCompareOption::QuickHash => Some(hash_file_quick(
+            file_path,
+            config.algorithm,
+            config.quick_bytes,
+            Some(&counters.bytes),
+            &config.cancel,
+        )?),
+        FileDataCompareOption::GitBlob => Some(hash_file_git_blob(
+            file_path,
+            config.io,
+            Some(&counters.bytes),
+            &config.cancel,
+        )?),
+    };
+
+    Ok(FileData {
+        path: file_path.into(),
+        size,
+        contents,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn folders(paths: &[&str]) -> BTreeMap<PathBuf, FilePath> {
+        paths
+            .iter()
+            .map(|p| (PathBuf::from(p), FilePath::from(Path::new(p))))
+            .collect()
+    }
+
+    fn names(only: &[FilePath]) -> Vec<String> {
+        only.iter().map(|f| f.0.display().to_string()).collect()
+    }
+
+    #[test]
+    fn cancelling_a_scan_leaves_the_next_one_alone() {
+        let root =
+            std::env::temp_dir().join(format!("folder_compare_scan_cancel_{}", std::process::id()));
+        let (a, b) = (root.join("a"), root.join("b"));
+        std::fs::create_dir_all(&a).unwrap();
+        std::fs::create_dir_all(&b).unwrap();
+        std::fs::write(a.join("f.txt"), "f").unwrap();
+
+        let cancelled = ScanBuilder::new(&a, &b);
+        cancelled.cancel_token().request();
+        let first = cancelled.scan().unwrap();
+        let second = ScanBuilder::new(&a, &b).scan().unwrap();
+        let _ = std::fs::remove_dir_all(&root);
+
+        assert!(first.is_incomplete());
+        assert_eq!(first.difference_count(), 0);
+        assert!(!second.is_incomplete());
+        assert_eq!(second.difference_count(), 1);
+    }
+
+    /// Records the events it is sent, and only asks for `Finished`
+    struct FinishedOnly(std::sync::Mutex<Vec<Event>>);
+
+    impl EventSink for FinishedOnly {
+        fn event(&self, event: Event) {
+            self.0.lock().unwrap().push(event);
+        }
+
+        fn wants(&self, kind: EventKind) -> bool {
+            kind == EventKind::Finished
+        }
+    }
+
+    #[test]
+    fn sinks_only_get_the_events_they_want() {
+        let root =
+            std::env::temp_dir().join(format!("folder_compare_scan_events_{}", std::process::id()));
+        let (a, b) = (root.join("a"), root.join("b"));
+        std::fs::create_dir_all(&a).unwrap();
+        std::fs::create_dir_all(&b).unwrap();
+        std::fs::write(a.join("f.txt"), "f").unwrap();
+
+        let sink = Arc::new(FinishedOnly(std::sync::Mutex::new(Vec::new())));
+        let config = Config {
+            folder1: a,
+            folder2: b,
+            events: Some(sink.clone()),
+            ..Config::default()
+        };
+        compare(&config, &Progress::new(false, 2)).unwrap();
+        let _ = std::fs::remove_dir_all(&root);
+
+        let events = sink.0.lock().unwrap();
+        assert_eq!(events.len(), 1);
+        assert!(matches!(
+            events[0],
+            Event::Finished {
+                differences: 1,
+                incomplete: false
+            }
+        ));
+    }
+
+    #[test]
+    fn folder_only_on_one_side_is_listed_once() {
+        let a = folders(&["A", "B", "B/C", "B/C/D"]);
+        let b = folders(&["A"]);
+        let only = folders_only_in(&a, &b, &HashSet::new());
+        assert_eq!(names(&only), ["B"]);
+    }
+
+    #[test]
+    fn folder_holding_a_matched_file_is_not_collapsed() {
+        let a = folders(&["B", "B/C", "B/E"]);
+        let b = folders(&[]);
+        let matched = HashSet::from([PathBuf::from("B"), PathBuf::from("B/C")]);
+        let only = folders_only_in(&a, &b, &matched);
+        assert_eq!(names(&only), ["B/E"]);
+    }
+}
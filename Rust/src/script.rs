@@ -0,0 +1,155 @@
+use std::io::Write;
+use std::path::Path;
+
+use crate::filedata::{ScriptFormat, SyncPolicy};
+use crate::sync::SyncAction;
+use crate::utils::VERSION;
+
+/// Write the planned actions as a script for an operator to review and run
+/// # Errors
+/// Will return an error if the output can't be written
+pub fn write_script(
+    out: &mut impl Write,
+    format: ScriptFormat,
+    policy: SyncPolicy,
+    actions: &[SyncAction],
+) -> anyhow::Result<()> {
+    match format {
+        ScriptFormat::Sh => write_sh(out, policy, actions)?,
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// POSIX shell. Written as bytes, so paths that aren't valid UTF-8 are reproduced exactly
+fn write_sh(
+    out: &mut impl Write,
+    policy: SyncPolicy,
+    actions: &[SyncAction],
+) -> std::io::Result<()> {
+    writeln!(out, "#!/bin/sh")?;
+    writeln!(
+        out,
+        "# Generated by folder_compare {}, policy {policy:?}. Review before running",
+        VERSION.unwrap_or("?")
+    )?;
+    writeln!(out, "set -eu")?;
+    writeln!(out)?;
+
+    for action in actions {
+        match action {
+            SyncAction::CreateDir(dir) => write_command(out, "mkdir -p --", &[dir])?,
+            SyncAction::Copy { from, to } | SyncAction::Replace { from, to } => {
+                write_command(out, "cp -p --", &[from, to])?;
+            }
+            SyncAction::Delete(path) => write_command(out, "rm -f --", &[path])?,
+        }
+    }
+
+    Ok(())
+}
+
+/// Write one command line, with each path quoted as a separate argument
+fn write_command(out: &mut impl Write, command: &str, paths: &[&Path]) -> std::io::Result<()> {
+    out.write_all(command.as_bytes())?;
+    for path in paths {
+        out.write_all(b" ")?;
+        out.write_all(&sh_quote(path))?;
+    }
+    out.write_all(b"\n")
+}
+
+/// Quote a path for POSIX shell. Inside single quotes every byte is literal except the quote itself,
+/// which is written as '\'' (close, escaped quote, reopen). Paths can't contain NUL, so this is always safe
+fn sh_quote(path: &Path) -> Vec<u8> {
+    let bytes = path.as_os_str().as_encoded_bytes();
+    let mut quoted = Vec::with_capacity(bytes.len() + 2);
+
+    quoted.push(b'\'');
+    for &byte in bytes {
+        if byte == b'\'' {
+            quoted.extend_from_slice(b"'\\''");
+        } else {
+            quoted.push(byte);
+        }
+    }
+    quoted.push(b'\'');
+
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// What the shell makes of a quoted path, printed back byte for byte
+    #[cfg(unix)]
+    fn through_sh(quoted: &[u8]) -> Vec<u8> {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let script = [b"printf %s ".as_slice(), quoted].concat();
+        let output = std::process::Command::new("sh")
+            .arg("-c")
+            .arg(OsStr::from_bytes(&script))
+            .output()
+            .expect("sh runs");
+        assert!(output.status.success());
+        output.stdout
+    }
+
+    #[test]
+    fn plain_path_is_wrapped_in_single_quotes() {
+        assert_eq!(sh_quote(Path::new("a b/c.txt")), b"'a b/c.txt'");
+    }
+
+    #[test]
+    fn single_quote_is_closed_escaped_and_reopened() {
+        assert_eq!(sh_quote(Path::new("it's")), b"'it'\\''s'");
+    }
+
+    #[test]
+    fn expansions_stay_literal() {
+        let path = Path::new("$(touch pwned) `id` $HOME *; rm -rf \\ \"x\"");
+        assert_eq!(
+            sh_quote(path),
+            b"'$(touch pwned) `id` $HOME *; rm -rf \\ \"x\"'"
+        );
+        #[cfg(unix)]
+        assert_eq!(
+            through_sh(&sh_quote(path)),
+            path.as_os_str().as_encoded_bytes()
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_bytes_are_kept() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let path = Path::new(OsStr::from_bytes(b"caf\xe9 '\xff\n.txt"));
+        assert_eq!(sh_quote(path), b"'caf\xe9 '\\''\xff\n.txt'");
+        assert_eq!(through_sh(&sh_quote(path)), path.as_os_str().as_bytes());
+    }
+
+    #[test]
+    fn script_quotes_every_path() {
+        let actions = [
+            SyncAction::CreateDir(Path::new("b/new dir").to_path_buf()),
+            SyncAction::Copy {
+                from: Path::new("a/it's").to_path_buf(),
+                to: Path::new("b/it's").to_path_buf(),
+            },
+            SyncAction::Delete(Path::new("b/$(x)").to_path_buf()),
+        ];
+        let mut out = Vec::new();
+        write_script(&mut out, ScriptFormat::Sh, SyncPolicy::Mirror, &actions).unwrap();
+        let text = String::from_utf8(out).unwrap();
+
+        assert!(text.starts_with("#!/bin/sh\n"));
+        assert!(text.contains("\nmkdir -p -- 'b/new dir'\n"));
+        assert!(text.contains("\ncp -p -- 'a/it'\\''s' 'b/it'\\''s'\n"));
+        assert!(text.ends_with("\nrm -f -- 'b/$(x)'\n"));
+    }
+}
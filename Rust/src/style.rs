@@ -0,0 +1,55 @@
+use std::fmt::Display;
+use std::io::IsTerminal;
+
+use folder_compare::ColorMode;
+
+/// Colours for each side and category of difference
+#[derive(Debug, Copy, Clone)]
+pub enum Paint {
+    OnlyInA, // green
+    OnlyInB, // red
+    Changed, // yellow
+    Heading, // bold
+}
+
+impl Paint {
+    /// The ANSI SGR parameter for the colour
+    fn code(self) -> &'static str {
+        match self {
+            Paint::OnlyInA => "32",
+            Paint::OnlyInB => "31",
+            Paint::Changed => "33",
+            Paint::Heading => "1",
+        }
+    }
+}
+
+/// Whether output is coloured
+pub struct Style {
+    enabled: bool,
+}
+
+impl Style {
+    /// Colour when asked to, or by default when stdout is a terminal and `NO_COLOR` isn't set to anything.
+    /// An explicit `--color=always` wins over `NO_COLOR`
+    pub fn new(mode: ColorMode) -> Self {
+        let enabled = match mode {
+            ColorMode::Always => true,
+            ColorMode::Never => false,
+            ColorMode::Auto => {
+                std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+                    && std::io::stdout().is_terminal()
+            }
+        };
+        Style { enabled }
+    }
+
+    /// The text in a colour, if colour is enabled
+    pub fn paint(&self, paint: Paint, text: impl Display) -> String {
+        if self.enabled {
+            format!("\x1b[{}m{text}\x1b[0m", paint.code())
+        } else {
+            text.to_string()
+        }
+    }
+}
@@ -0,0 +1,261 @@
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::OsString;
+use std::fmt::{Display, Formatter};
+use std::path::{MAIN_SEPARATOR, Path};
+
+use crate::comparer::FileData;
+use crate::filedata::FilePath;
+use crate::utils::{format_bytes, format_count, relative_path};
+
+/// How many differing files are under a folder, and their total size
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Totals {
+    pub files: usize,
+    pub bytes: u64,
+}
+
+impl Totals {
+    /// The count and total size of some files
+    #[must_use]
+    pub fn of<'a>(files: impl IntoIterator<Item = &'a FileData>) -> Self {
+        files
+            .into_iter()
+            .fold(Totals::default(), |mut totals, file| {
+                totals.add(file.size);
+                totals
+            })
+    }
+
+    fn add(&mut self, size: u64) {
+        self.files += 1;
+        self.bytes += size;
+    }
+}
+
+impl Display for Totals {
+    /// eg `1,234 files, 5.6 GB`
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.files {
+            1 => write!(f, "1 file, {}", format_bytes(self.bytes)),
+            n => write!(f, "{} files, {}", format_count(n), format_bytes(self.bytes)),
+        }
+    }
+}
+
+/// The totals of the differing files inside each of the folders, in the same order. Folders only on one side
+/// never nest, so each file counts towards one folder at most
+#[must_use]
+pub fn folder_totals<'a>(
+    folders: &[&FilePath],
+    files: impl IntoIterator<Item = &'a FileData>,
+) -> Vec<Totals> {
+    let index: HashMap<&Path, usize> = folders
+        .iter()
+        .enumerate()
+        .map(|(i, folder)| (folder.0.as_path(), i))
+        .collect();
+
+    let mut totals = vec![Totals::default(); folders.len()];
+    for file in files {
+        if let Some(&i) = file.path.0.ancestors().find_map(|a| index.get(a)) {
+            totals[i].add(file.size);
+        }
+    }
+    totals
+}
+
+/// The differences on one side as a tree of folders, each with the totals of the differing files inside it
+#[derive(Debug, Default)]
+pub struct DiffTree {
+    pub totals: Totals,
+    pub missing: bool,          // a folder only on this side
+    pub file_size: Option<u64>, // set for a file
+    pub children: BTreeMap<OsString, DiffTree>,
+}
+
+impl DiffTree {
+    /// Build the tree of differences under `root`. Folders only on this side are marked, and the files inside
+    /// them are counted but only listed if `expand` is set
+    #[must_use]
+    pub fn new(root: &Path, folders: &[&FilePath], files: &[&FileData], expand: bool) -> Self {
+        let mut tree = DiffTree::default();
+        for folder in folders {
+            tree.node(&relative_path(root, &folder.0)).missing = true;
+        }
+
+        for file in files {
+            let relative = relative_path(root, &file.path.0);
+            let last = relative.iter().count().saturating_sub(1);
+
+            let mut node = &mut tree;
+            node.totals.add(file.size);
+            for (i, name) in relative.iter().enumerate() {
+                node = node.children.entry(name.to_os_string()).or_default();
+                if i == last {
+                    node.file_size = Some(file.size);
+                    break;
+                }
+                node.totals.add(file.size);
+                if node.missing && !expand {
+                    break;
+                }
+            }
+        }
+        tree
+    }
+
+    /// The node at a relative path, creating it and any above it
+    fn node(&mut self, relative: &Path) -> &mut DiffTree {
+        relative.iter().fold(self, |node, name| {
+            node.children.entry(name.to_os_string()).or_default()
+        })
+    }
+
+    /// One line per file and folder below the root, indented two spaces per level
+    #[must_use]
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        self.add_lines(0, &mut lines);
+        lines
+    }
+
+    fn add_lines(&self, depth: usize, lines: &mut Vec<String>) {
+        let indent = "  ".repeat(depth);
+        for (name, child) in &self.children {
+            let name = name.to_string_lossy();
+            lines.push(match (child.file_size, child.missing) {
+                (Some(size), _) => format!("{indent}{name} ({})", format_bytes(size)),
+                (None, true) => format!(
+                    "{indent}{name}{MAIN_SEPARATOR} (whole folder, {})",
+                    child.totals
+                ),
+                (None, false) => format!("{indent}{name}{MAIN_SEPARATOR} ({})", child.totals),
+            });
+            child.add_lines(depth + 1, lines);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(path: &str, size: u64) -> FileData {
+        FileData {
+            path: FilePath(path.into()),
+            size,
+            contents: None,
+        }
+    }
+
+    #[test]
+    fn totals_per_folder() {
+        let folders = [FilePath("/r/x".into()), FilePath("/r/y".into())];
+        let files = [
+            file("/r/x/one", 10),
+            file("/r/x/deep/two", 20),
+            file("/r/y/three", 5),
+            file("/r/outside", 7),
+            file("/r/xy/not_in_x", 9),
+        ];
+        let folders: Vec<&FilePath> = folders.iter().collect();
+        assert_eq!(
+            folder_totals(&folders, &files),
+            [
+                Totals {
+                    files: 2,
+                    bytes: 30
+                },
+                Totals { files: 1, bytes: 5 }
+            ]
+        );
+        assert_eq!(
+            Totals::of(&files),
+            Totals {
+                files: 5,
+                bytes: 51
+            }
+        );
+        assert_eq!(Totals { files: 1, bytes: 5 }.to_string(), "1 file, 5 B");
+    }
+
+    /// The node at a relative path
+    fn at<'t>(tree: &'t DiffTree, relative: &str) -> &'t DiffTree {
+        Path::new(relative)
+            .iter()
+            .fold(tree, |node, name| &node.children[name])
+    }
+
+    fn tree(expand: bool) -> DiffTree {
+        let gone = FilePath("/r/gone".into());
+        let files = [
+            file("/r/a/b/f1", 10),
+            file("/r/a/f2", 20),
+            file("/r/gone/g1", 1),
+            file("/r/gone/sub/g2", 2),
+            file("/r/top", 3),
+        ];
+        let files: Vec<&FileData> = files.iter().collect();
+        DiffTree::new(Path::new("/r"), &[&gone], &files, expand)
+    }
+
+    #[test]
+    fn nested_tree_with_totals() {
+        let tree = tree(false);
+        assert_eq!(
+            tree.totals,
+            Totals {
+                files: 5,
+                bytes: 36
+            }
+        );
+        assert_eq!(
+            at(&tree, "a").totals,
+            Totals {
+                files: 2,
+                bytes: 30
+            }
+        );
+        assert_eq!(at(&tree, "a/b/f1").file_size, Some(10));
+
+        let sep = MAIN_SEPARATOR;
+        assert_eq!(
+            tree.lines(),
+            [
+                format!("a{sep} (2 files, 30 B)"),
+                format!("  b{sep} (1 file, 10 B)"),
+                "    f1 (10 B)".to_string(),
+                "  f2 (20 B)".to_string(),
+                format!("gone{sep} (whole folder, 2 files, 3 B)"),
+                "top (3 B)".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_folders_only_list_their_files_when_expanded() {
+        let tree = tree(true);
+        assert_eq!(
+            tree.totals,
+            Totals {
+                files: 5,
+                bytes: 36
+            }
+        );
+        assert!(at(&tree, "gone").missing);
+        assert_eq!(at(&tree, "gone").totals, Totals { files: 2, bytes: 3 });
+
+        let sep = MAIN_SEPARATOR;
+        let lines = tree.lines();
+        assert_eq!(
+            lines[4..7],
+            [
+                format!("gone{sep} (whole folder, 2 files, 3 B)"),
+                "  g1 (1 B)".to_string(),
+                format!("  sub{sep} (1 file, 2 B)"),
+            ]
+        );
+        assert_eq!(lines[7], "    g2 (2 B)");
+        assert_eq!(lines.len(), 9);
+    }
+}
@@ -0,0 +1,644 @@
+use filetime::FileTime;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use std::collections::BTreeMap;
+use walkdir::{DirEntry, WalkDir};
+
+use crate::cancel::CancelToken;
+use crate::comparer::NameForm;
+use crate::dedupe::files_identical;
+use crate::filedata::{FilePath, SyncPolicy};
+use crate::scan::walk_error_message;
+use crate::utils::{Config, hash_file};
+
+/// A single filesystem change, planned before anything is touched
+#[derive(Debug, Clone)]
+pub enum SyncAction {
+    /// Create a directory, and any missing parents
+    CreateDir(PathBuf),
+    /// Copy a file to a destination that doesn't exist yet
+    Copy { from: PathBuf, to: PathBuf },
+    /// Overwrite an existing file that differs from its source
+    Replace { from: PathBuf, to: PathBuf },
+    /// Remove a file that has no counterpart in the source
+    Delete(PathBuf),
+}
+
+/// Plan the actions for a policy, always changing folder B to look more like folder A.
+/// `diff1` and `diff2` are the files only in A and only in B, by the configured comparison
+/// # Errors
+/// Will return an error if a folder can't be read
+pub fn plan_policy(
+    config: &Config,
+    policy: SyncPolicy,
+    diff1: &[&FilePath],
+    diff2: &[&FilePath],
+) -> anyhow::Result<Vec<SyncAction>> {
+    match policy {
+        SyncPolicy::CopyMissing => plan_copy_missing(diff1, &config.folder1, &config.folder2),
+        SyncPolicy::DeleteExtra => Ok(plan_delete_extra(diff2)),
+        SyncPolicy::Mirror => plan_mirror(config, &config.folder1, &config.folder2),
+    }
+}
+
+/// Plan copying the given files from `src_root` into the same relative locations under `dst_root`.
+/// Files already present at the destination path are skipped, never overwritten
+/// # Errors
+/// Will return an error if a file is not inside `src_root`
+pub fn plan_copy_missing(
+    missing: &[&FilePath],
+    src_root: &Path,
+    dst_root: &Path,
+) -> anyhow::Result<Vec<SyncAction>> {
+    let mut actions = Vec::with_capacity(missing.len());
+    let mut dirs_planned = Vec::new();
+
+    // sorted, so the log reads in tree order and parent directories come first
+    let mut sources: Vec<&Path> = missing.iter().map(|f| f.0.as_path()).collect();
+    sources.sort_unstable();
+
+    for from in sources {
+        let relative = from.strip_prefix(src_root)?;
+        let to = dst_root.join(relative);
+
+        if to.exists() {
+            log_action("SKIP", &format!("{} (already exists)", to.display()));
+            continue;
+        }
+
+        plan_copy(&mut actions, &mut dirs_planned, from, to);
+    }
+
+    Ok(actions)
+}
+
+/// Plan deleting the given files, which are only in B
+#[must_use]
+pub fn plan_delete_extra(extra: &[&FilePath]) -> Vec<SyncAction> {
+    let mut paths: Vec<&Path> = extra.iter().map(|f| f.0.as_path()).collect();
+    paths.sort_unstable();
+
+    paths
+        .into_iter()
+        .map(|p| SyncAction::Delete(p.to_path_buf()))
+        .collect()
+}
+
+/// Plan making `dst_root` match `src_root` by relative path. Files only in the source are copied,
+/// files whose contents differ are replaced, and files only in the destination are deleted. Contents are
+/// compared byte for byte whatever the comparison, since a name match says nothing about them. Both trees
+/// are walked again, because the comparison only holds one file per match
+/// # Errors
+/// Will return an error if anything in either tree can't be read, since a folder that can't be read in the
+/// source would otherwise have its files deleted from the destination, or if a file can't be read to compare it
+pub fn plan_mirror(
+    config: &Config,
+    src_root: &Path,
+    dst_root: &Path,
+) -> anyhow::Result<Vec<SyncAction>> {
+    let form = config.name_form();
+    let src_files = relative_files(src_root, form)?;
+    let dst_files = relative_files(dst_root, form)?;
+
+    let mut actions = Vec::new();
+    let mut dirs_planned = Vec::new();
+
+    for (relative, src_entry) in &src_files {
+        match dst_files.get(relative) {
+            // the key may be folded, so the copy keeps the source's own name
+            None => plan_copy(
+                &mut actions,
+                &mut dirs_planned,
+                src_entry.path(),
+                dst_root.join(src_entry.path().strip_prefix(src_root)?),
+            ),
+            Some(dst_entry) => {
+                if !same_contents(src_entry, dst_entry, &config.cancel)? {
+                    actions.push(SyncAction::Replace {
+                        from: src_entry.path().to_path_buf(),
+                        to: dst_entry.path().to_path_buf(),
+                    });
+                }
+            }
+        }
+    }
+
+    for (relative, dst_entry) in &dst_files {
+        if !src_files.contains_key(relative) {
+            actions.push(SyncAction::Delete(dst_entry.path().to_path_buf()));
+        }
+    }
+
+    Ok(actions)
+}
+
+/// Same size, then identical byte for byte
+fn same_contents(a: &DirEntry, b: &DirEntry, cancel: &CancelToken) -> anyhow::Result<bool> {
+    Ok(a.metadata()?.len() == b.metadata()?.len() && files_identical(a.path(), b.path(), cancel)?)
+}
+
+/// All the files under a root, keyed and sorted by their path relative to it, folded as names are compared
+/// # Errors
+/// Will return an error on the first file or folder that can't be read
+fn relative_files(root: &Path, form: NameForm) -> anyhow::Result<BTreeMap<PathBuf, DirEntry>> {
+    let mut files = BTreeMap::new();
+    for entry in WalkDir::new(root) {
+        let entry = entry.map_err(|e| {
+            let path = e.path().unwrap_or(root);
+            anyhow::anyhow!(
+                "Cannot read '{}': {}, nothing mirrored",
+                path.display(),
+                walk_error_message(&e)
+            )
+        })?;
+        if entry.file_type().is_file()
+            && let Ok(relative) = entry.path().strip_prefix(root)
+        {
+            files.insert(form.apply_path(relative), entry);
+        }
+    }
+    Ok(files)
+}
+
+/// Plan a copy, preceded by creating the destination directory if it doesn't exist and isn't already planned
+fn plan_copy(
+    actions: &mut Vec<SyncAction>,
+    dirs_planned: &mut Vec<PathBuf>,
+    from: &Path,
+    to: PathBuf,
+) {
+    if let Some(parent) = to.parent()
+        && !parent.is_dir()
+        && !dirs_planned.iter().any(|d| d == parent)
+    {
+        dirs_planned.push(parent.to_path_buf());
+        actions.push(SyncAction::CreateDir(parent.to_path_buf()));
+    }
+
+    actions.push(SyncAction::Copy {
+        from: from.to_path_buf(),
+        to,
+    });
+}
+
+/// The folder being changed, and where deleted or replaced files are moved to
+pub struct SyncTarget<'a> {
+    pub root: &'a Path,
+    pub quarantine: Option<PathBuf>, // None deletes permanently
+}
+
+/// Counts of each kind of action in a plan, for confirmation and limits
+#[derive(Debug, Default)]
+pub struct PlanSummary {
+    pub dirs: usize,
+    pub copies: usize,
+    pub replaces: usize,
+    pub deletes: usize,
+}
+
+impl PlanSummary {
+    #[must_use]
+    pub fn new(actions: &[SyncAction]) -> Self {
+        let mut summary = PlanSummary::default();
+        for action in actions {
+            match action {
+                SyncAction::CreateDir(_) => summary.dirs += 1,
+                SyncAction::Copy { .. } => summary.copies += 1,
+                SyncAction::Replace { .. } => summary.replaces += 1,
+                SyncAction::Delete(_) => summary.deletes += 1,
+            }
+        }
+        summary
+    }
+}
+
+/// Refuse a plan that would delete more than `max_delete` files
+/// # Errors
+/// Will return an error if the limit is exceeded
+pub fn check_delete_limit(summary: &PlanSummary, max_delete: Option<usize>) -> anyhow::Result<()> {
+    match max_delete {
+        Some(max) if summary.deletes > max => Err(anyhow::anyhow!(
+            "Plan would delete {} file(s), more than --max-delete {max}. Aborting, nothing changed",
+            summary.deletes
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Ask the operator to confirm the plan on stderr/stdin. Anything but y or yes declines
+/// # Errors
+/// Will return an error if stdin can't be read
+pub fn confirm(summary: &PlanSummary, target: &SyncTarget) -> anyhow::Result<bool> {
+    let deletes = match &target.quarantine {
+        Some(q) => format!("move {} file(s) to '{}'", summary.deletes, q.display()),
+        None => format!("permanently delete {} file(s)", summary.deletes),
+    };
+    eprint!(
+        "Mirror will create {} folder(s), copy {} file(s), replace {} file(s) and {deletes} in '{}'. Continue? [y/N] ",
+        summary.dirs,
+        summary.copies,
+        summary.replaces,
+        target.root.display()
+    );
+
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(matches!(
+        answer.trim().to_ascii_lowercase().as_str(),
+        "y" | "yes"
+    ))
+}
+
+/// Default quarantine for a target folder: a sibling `<name>.quarantine` folder, with a subfolder per run
+/// so earlier quarantined files are never overwritten
+#[must_use]
+pub fn default_quarantine(root: &Path) -> PathBuf {
+    let name = root
+        .file_name()
+        .map_or_else(|| "root".into(), |n| n.to_string_lossy().into_owned());
+    let stamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+
+    root.with_file_name(format!("{name}.quarantine"))
+        .join(stamp.to_string())
+}
+
+/// Carry out the planned actions, logging each one. With `dry_run` nothing is changed.
+/// Cancellation skips the remaining actions. Returns the number of actions that failed
+#[must_use]
+pub fn execute(
+    config: &Config,
+    actions: &[SyncAction],
+    dry_run: bool,
+    target: &SyncTarget,
+) -> usize {
+    let mut failures = 0;
+
+    for action in actions {
+        if config.cancel.is_cancelled() {
+            log_action("CANCELLED", "remaining actions skipped");
+            break;
+        }
+
+        let (verb, detail) = describe(action);
+        if dry_run {
+            log_action(&format!("DRY-RUN {verb}"), &detail);
+            continue;
+        }
+
+        match apply(config, action, target) {
+            Ok(()) => log_action(verb, &detail),
+            Err(e) => {
+                log_action("FAILED", &format!("{verb} {detail}: {e}"));
+                failures += 1;
+            }
+        }
+    }
+
+    failures
+}
+
+/// Short verb and detail text for an action, for the log
+fn describe(action: &SyncAction) -> (&'static str, String) {
+    match action {
+        SyncAction::CreateDir(dir) => ("MKDIR", dir.display().to_string()),
+        SyncAction::Copy { from, to } => {
+            ("COPY", format!("{} -> {}", from.display(), to.display()))
+        }
+        SyncAction::Replace { from, to } => {
+            ("REPLACE", format!("{} -> {}", from.display(), to.display()))
+        }
+        SyncAction::Delete(path) => ("DELETE", path.display().to_string()),
+    }
+}
+
+/// Perform one action
+fn apply(config: &Config, action: &SyncAction, target: &SyncTarget) -> anyhow::Result<()> {
+    match action {
+        SyncAction::CreateDir(dir) => fs::create_dir_all(dir)?,
+        SyncAction::Copy { from, to } => copy_verified(config, from, to)?,
+        SyncAction::Replace { from, to } => replace_verified(config, from, to, target)?,
+        SyncAction::Delete(path) => remove(path, target)?,
+    }
+    Ok(())
+}
+
+/// Replace a file with a verified copy. The copy is made alongside and renamed over the original,
+/// so the original is only touched once the new contents are proven
+fn replace_verified(
+    config: &Config,
+    from: &Path,
+    to: &Path,
+    target: &SyncTarget,
+) -> anyhow::Result<()> {
+    let mut temp_name = OsString::from(".");
+    temp_name.push(to.file_name().unwrap_or_default());
+    temp_name.push(".folder_compare.tmp");
+    let temp = to.with_file_name(temp_name);
+
+    copy_verified(config, from, &temp)?;
+
+    let result = remove(to, target).and_then(|()| Ok(fs::rename(&temp, to)?));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
+
+/// Move a file into the quarantine, keeping its path relative to the target root. Without a quarantine
+/// the file is deleted permanently
+fn remove(path: &Path, target: &SyncTarget) -> anyhow::Result<()> {
+    let Some(quarantine) = &target.quarantine else {
+        fs::remove_file(path)?;
+        return Ok(());
+    };
+
+    let relative = path.strip_prefix(target.root)?;
+    let dest = quarantine.join(relative);
+    if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    // rename only works within one filesystem, otherwise fall back to copy and delete
+    if fs::rename(path, &dest).is_err() {
+        fs::copy(path, &dest)?;
+        copy_times(path, &dest)?;
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+/// Copy a file, preserving permissions and modification time, then re-hash both ends to prove the copy.
+/// A copy that fails verification is removed
+fn copy_verified(config: &Config, from: &Path, to: &Path) -> anyhow::Result<()> {
+    // fs::copy carries the permission bits across
+    fs::copy(from, to)?;
+    copy_times(from, to)?;
+
+    let verified = hash_file(from, config.algorithm, config.io, None, &config.cancel).and_then(
+        |source_hash| {
+            let copy_hash = hash_file(to, config.algorithm, config.io, None, &config.cancel)?;
+            Ok(source_hash == copy_hash)
+        },
+    );
+
+    // an unverified copy is never left behind, including when verification was cancelled
+    match verified {
+        Ok(true) => Ok(()),
+        Ok(false) => {
+            fs::remove_file(to)?;
+            Err(anyhow::anyhow!("verification failed, copy removed"))
+        }
+        Err(e) => {
+            fs::remove_file(to)?;
+            Err(e.context("could not verify, copy removed"))
+        }
+    }
+}
+
+/// Set the access and modification times of `to` to match `from`. Path based, so it works on read-only copies
+/// # Errors
+/// Will return an error if the times cannot be read or set
+pub fn copy_times(from: &Path, to: &Path) -> anyhow::Result<()> {
+    let metadata = fs::metadata(from)?;
+    filetime::set_file_times(
+        to,
+        FileTime::from_last_access_time(&metadata),
+        FileTime::from_last_modification_time(&metadata),
+    )?;
+    Ok(())
+}
+
+/// Every action is logged to stderr, keeping stdout for the comparison results
+pub fn log_action(verb: &str, detail: &str) {
+    eprintln!("{verb} {detail}");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filedata::FileDataCompareOption;
+
+    /// A fresh folder pair under the temp folder, removed when dropped
+    struct TempTrees(PathBuf);
+
+    impl TempTrees {
+        fn new(name: &str, a: &[(&str, &str)], b: &[(&str, &str)]) -> Self {
+            let root = std::env::temp_dir()
+                .join(format!("folder_compare_sync_{name}_{}", std::process::id()));
+            let _ = fs::remove_dir_all(&root);
+            for (side, files) in [("a", a), ("b", b)] {
+                fs::create_dir_all(root.join(side)).unwrap();
+                for (path, contents) in files {
+                    let path = root.join(side).join(path);
+                    fs::create_dir_all(path.parent().unwrap()).unwrap();
+                    fs::write(path, contents).unwrap();
+                }
+            }
+            TempTrees(root)
+        }
+
+        fn config(&self) -> Config {
+            Config {
+                folder1: self.0.join("a"),
+                folder2: self.0.join("b"),
+                ..Config::default()
+            }
+        }
+    }
+
+    impl Drop for TempTrees {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// Each action as a verb and paths relative to the trees, for comparing with what is expected
+    fn summarise(trees: &TempTrees, actions: &[SyncAction]) -> Vec<String> {
+        let relative = |path: &Path| {
+            path.strip_prefix(&trees.0)
+                .unwrap()
+                .to_string_lossy()
+                .replace('\\', "/")
+        };
+        actions
+            .iter()
+            .map(|action| match action {
+                SyncAction::CreateDir(dir) => format!("mkdir {}", relative(dir)),
+                SyncAction::Copy { from, to } => {
+                    format!("copy {} {}", relative(from), relative(to))
+                }
+                SyncAction::Replace { from, to } => {
+                    format!("replace {} {}", relative(from), relative(to))
+                }
+                SyncAction::Delete(path) => format!("delete {}", relative(path)),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn mirror_replaces_changed_contents_whatever_the_comparison() {
+        let trees = TempTrees::new(
+            "changed",
+            &[("f.txt", "new"), ("same.txt", "same"), ("g.txt", "abc")],
+            &[("f.txt", "old"), ("same.txt", "same"), ("g.txt", "abd")],
+        );
+
+        for comparer in [
+            FileDataCompareOption::Name,
+            FileDataCompareOption::NameSize,
+            FileDataCompareOption::Stem,
+        ] {
+            let config = Config {
+                comparer,
+                ..trees.config()
+            };
+            let actions = plan_mirror(&config, &config.folder1, &config.folder2).unwrap();
+            assert_eq!(
+                summarise(&trees, &actions),
+                ["replace a/f.txt b/f.txt", "replace a/g.txt b/g.txt"],
+                "{comparer:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn mirror_copies_missing_and_deletes_extra() {
+        let trees = TempTrees::new(
+            "missing",
+            &[("keep.txt", "k"), ("sub/new.txt", "n")],
+            &[("keep.txt", "k"), ("extra.txt", "x")],
+        );
+        let config = trees.config();
+
+        let actions = plan_mirror(&config, &config.folder1, &config.folder2).unwrap();
+        assert_eq!(
+            summarise(&trees, &actions),
+            [
+                "mkdir b/sub",
+                "copy a/sub/new.txt b/sub/new.txt",
+                "delete b/extra.txt"
+            ]
+        );
+    }
+
+    #[test]
+    fn copy_missing_copies_files_sharing_a_name() {
+        let trees = TempTrees::new(
+            "copy_duplicates",
+            &[("x/readme.txt", "x"), ("y/readme.txt", "y")],
+            &[],
+        );
+        let config = Config {
+            comparer: FileDataCompareOption::Name,
+            ..trees.config()
+        };
+        let result =
+            crate::scan::compare(&config, &crate::progress::Progress::new(false, 2)).unwrap();
+        let missing: Vec<&FilePath> = result.all_only_in_a().collect();
+
+        let actions = plan_copy_missing(&missing, &config.folder1, &config.folder2).unwrap();
+        assert_eq!(
+            summarise(&trees, &actions),
+            [
+                "mkdir b/x",
+                "copy a/x/readme.txt b/x/readme.txt",
+                "mkdir b/y",
+                "copy a/y/readme.txt b/y/readme.txt"
+            ]
+        );
+    }
+
+    #[test]
+    fn copy_missing_never_overwrites() {
+        let trees = TempTrees::new(
+            "copy_existing",
+            &[("f.txt", "new"), ("g.txt", "g")],
+            &[("f.txt", "old")],
+        );
+        let config = trees.config();
+        let missing = [
+            FilePath(config.folder1.join("f.txt")),
+            FilePath(config.folder1.join("g.txt")),
+        ];
+        let missing: Vec<&FilePath> = missing.iter().collect();
+
+        let actions = plan_copy_missing(&missing, &config.folder1, &config.folder2).unwrap();
+        assert_eq!(summarise(&trees, &actions), ["copy a/g.txt b/g.txt"]);
+
+        let target = SyncTarget {
+            root: &config.folder2,
+            quarantine: None,
+        };
+        assert_eq!(execute(&config, &actions, false, &target), 0);
+        assert_eq!(
+            fs::read_to_string(config.folder2.join("f.txt")).unwrap(),
+            "old"
+        );
+        assert_eq!(
+            fs::read_to_string(config.folder2.join("g.txt")).unwrap(),
+            "g"
+        );
+    }
+
+    #[test]
+    fn mirror_aborts_when_the_source_cannot_be_read() {
+        let trees = TempTrees::new("missing_root", &[], &[("f.txt", "keep")]);
+        let config = Config {
+            folder1: trees.0.join("gone"),
+            ..trees.config()
+        };
+
+        let error = plan_mirror(&config, &config.folder1, &config.folder2).unwrap_err();
+        assert!(error.to_string().contains("nothing mirrored"), "{error}");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn mirror_aborts_when_a_source_folder_cannot_be_read() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let trees = TempTrees::new(
+            "unreadable",
+            &[("sub/f.txt", "a")],
+            &[("sub/f.txt", "a"), ("top.txt", "t")],
+        );
+        let config = trees.config();
+        let sub = config.folder1.join("sub");
+        fs::set_permissions(&sub, fs::Permissions::from_mode(0o000)).unwrap();
+        // root can read it anyway, so there is nothing to test
+        let readable = fs::read_dir(&sub).is_ok();
+
+        let planned = plan_mirror(&config, &config.folder1, &config.folder2);
+        fs::set_permissions(&sub, fs::Permissions::from_mode(0o755)).unwrap();
+        if !readable {
+            let error = planned.unwrap_err();
+            assert!(error.to_string().contains("nothing mirrored"), "{error}");
+        }
+    }
+
+    #[test]
+    fn mirror_execute_replaces_the_file() {
+        let trees = TempTrees::new("execute", &[("f.txt", "new")], &[("f.txt", "old")]);
+        let config = trees.config();
+        let target = SyncTarget {
+            root: &config.folder2,
+            quarantine: Some(trees.0.join("quarantine")),
+        };
+
+        let actions = plan_mirror(&config, &config.folder1, &config.folder2).unwrap();
+        assert_eq!(execute(&config, &actions, false, &target), 0);
+        assert_eq!(
+            fs::read_to_string(config.folder2.join("f.txt")).unwrap(),
+            "new"
+        );
+        assert_eq!(
+            fs::read_to_string(trees.0.join("quarantine/f.txt")).unwrap(),
+            "old"
+        );
+    }
+}
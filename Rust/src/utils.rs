@@ -1,146 +1,611 @@
-use git_version::git_version;
-use sha2::Digest;
-use std::collections::HashMap;
 use std::fs::File;
-use std::io::{BufReader, Read};
+use std::io::{BufReader, Read, Seek, SeekFrom};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use crate::filedata::{FileDataCompareOption, Sha2Hash};
-use crate::{FilePath, parse_comparer};
+use crate::archive::ArchiveKind;
+use crate::cancel::CancelToken;
+use crate::comparer::{FileComparer, NameForm, NameKey, NameTransform, builtin_comparer};
+use crate::events::{Event, EventKind, SharedEventSink};
+use crate::filedata::{
+    ColorMode, DedupeMode, FileDataCompareOption, HashAlgorithm, HashKey, IoStrategy, MetaField,
+    NameCase, NameCheckScope, NameNormalize, ScriptFormat, SyncPolicy,
+};
+use crate::git::GitSource;
+use crate::hasher::FileHasher;
 
 pub const VERSION: Option<&str> = option_env!("CARGO_PKG_VERSION");
-pub const GIT_VERSION: &str = git_version!(args = ["--abbrev=40", "--always", "--dirty=+"]);
 const FILE_BUFFER_SIZE: usize = 4096;
-
-pub const HELP: &str = "\
-USAGE:
-    folder_compare -a <folder> -b <folder> [-c <comparison>] [-r] [-f]
-
-MANDATORY PARAMETERS:
-    -a, --foldera                First folder to compare
-    -b, --folderb                Second folder to compare
-
-OPTIONS:
-    -c, --comparison [value]     Comparison to use.
-    -r, --raw                    Raw output, for piping
-    -o, --one-thread             Only use one thread, don't scan folders in parallel
-    -f, --first-only             Only show files in folder A missing from folder B (default is both)
-    
-Comparison can be:
-    Name, NameSize or Hash. Default is Name.";
+const LARGE_BUFFER_SIZE: usize = 1024 * 1024;
+const BUFFER_ALIGNMENT: usize = 4096;
+const LARGE_IO_THRESHOLD: u64 = 16 * 1024; // crossover point for Auto, see Benchmarks.md
+pub const DEFAULT_QUICK_KIB: u64 = 64;
 
 /// Configuration for the program, wrapper around various options
+#[allow(clippy::struct_excessive_bools)]
 pub struct Config {
     pub folder1: PathBuf,
     pub folder2: PathBuf,
-    pub comparer: FileDataCompareOption, // how to compare files, Name, NameSize or Hash
+    pub extra_folders: Vec<PathBuf>, // further folders for an N-way comparison
+    pub comparer: FileDataCompareOption, // how to compare files, Name, NameSize, Stem, Hash, QuickHash or GitBlob
+    pub algorithm: HashAlgorithm,        // hash algorithm for keys and file contents
+    pub io: IoStrategy,                  // how file contents are read when hashing
     pub raw: bool,                       // raw output, for piping
+    pub color: ColorMode,                // when the output is coloured, never for raw output
+    pub full_paths: bool,                // show full paths rather than paths relative to the folder
     pub first_only: bool, // only show files in folder A missing from folder B (default is both)
-    pub one_thread: bool, // only use one thread, don't scan folders in parallel
+    pub expand: bool,     // list every file inside a folder only on one side, not just the folder
+    pub raw_folders: bool, // raw output lists folders only on one side, standing for the files inside them
+    pub tree: bool,        // show the differences as an indented tree of folders with totals
+    pub report: Option<PathBuf>, // write an HTML report of the differences to this file
+    pub one_thread: bool,  // only use one thread, don't scan folders in parallel
+    pub quick_bytes: u64,  // bytes read from the head and tail of each file for QuickHash
+    pub verify: bool,      // fully hash QuickHash matches, to confirm them
+    pub copy_missing_to_b: bool, // copy files only in A into B
+    pub copy_missing_to_a: bool, // copy files only in B into A
+    pub dry_run: bool,     // log sync actions without performing them
+    pub emit_script: Option<ScriptFormat>, // write a script of sync actions instead of the results
+    pub policy: SyncPolicy, // what the emitted script does
+    pub mirror: bool,      // make B match A, including deletions
+    pub quarantine: Option<PathBuf>, // where mirror moves deleted files, None for the default
+    pub no_quarantine: bool, // mirror deletes permanently
+    pub max_delete: Option<usize>, // abort if a plan deletes more than this
+    pub yes: bool,         // skip the mirror confirmation
+    pub dedupe: Option<DedupeMode>, // replace identical files with hardlinks or reflinks
+    pub descend_archives: bool, // compare the members of archives found in the folders
+    pub list_a: Option<PathBuf>, // read A's files from this list instead of walking, - for stdin
+    pub list_b: Option<PathBuf>, // read B's files from this list instead of walking, - for stdin
+    pub name_case: NameCase, // whether case matters when comparing names and relative paths
+    pub name_normalize: NameNormalize, // Unicode normalisation of names and relative paths before comparing
+    pub name_transforms: Vec<NameTransform>, // regex substitutions applied to names before comparing
+    pub check_names: Option<NameCheckScope>, // report clashing and Windows-invalid names instead of comparing
+    pub check_meta: Vec<MetaField>, // metadata compared between files at the same relative path, empty for none
+    pub mtime_tolerance: Duration, // modification times this close are equal, for FAT's 2 second granularity
+    pub custom_comparer: Option<FileComparer>, // registered through the library, replaces the built-in comparer
+    pub events: Option<SharedEventSink>, // registered through the library, receives events as the scan runs
+    pub cancel: CancelToken, // stops this scan early, and keeps anything acting on its results from starting
+}
+
+/// Written out, rather than derived, so `QuickHash` reads 64 KiB from each end rather than nothing
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            folder1: PathBuf::new(),
+            folder2: PathBuf::new(),
+            extra_folders: Vec::new(),
+            comparer: FileDataCompareOption::default(),
+            algorithm: HashAlgorithm::default(),
+            io: IoStrategy::default(),
+            raw: false,
+            color: ColorMode::default(),
+            full_paths: false,
+            first_only: false,
+            expand: false,
+            raw_folders: false,
+            tree: false,
+            report: None,
+            one_thread: false,
+            quick_bytes: DEFAULT_QUICK_KIB * 1024,
+            verify: false,
+            copy_missing_to_b: false,
+            copy_missing_to_a: false,
+            dry_run: false,
+            emit_script: None,
+            policy: SyncPolicy::default(),
+            mirror: false,
+            quarantine: None,
+            no_quarantine: false,
+            max_delete: None,
+            yes: false,
+            dedupe: None,
+            descend_archives: false,
+            list_a: None,
+            list_b: None,
+            name_case: NameCase::default(),
+            name_normalize: NameNormalize::default(),
+            name_transforms: Vec::new(),
+            check_names: None,
+            check_meta: Vec::new(),
+            mtime_tolerance: Duration::ZERO,
+            custom_comparer: None,
+            events: None,
+            cancel: CancelToken::new(),
+        }
+    }
 }
 
-/// Hash a file using the given hasher as a Digest implementation
-/// Returns a `Sha2Hash`, which is a wrapper around a [u8; 32]
+impl Config {
+    /// Every folder being compared, A and B first
+    pub fn roots(&self) -> Vec<&Path> {
+        [self.folder1.as_path(), self.folder2.as_path()]
+            .into_iter()
+            .chain(self.extra_folders.iter().map(PathBuf::as_path))
+            .collect()
+    }
+
+    /// The list of files given for a side, 0 for A and 1 for B, if it isn't to be walked
+    #[must_use]
+    pub fn file_list(&self, side: usize) -> Option<&Path> {
+        match side {
+            0 => self.list_a.as_deref(),
+            1 => self.list_b.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// True if any root is an archive or git revision rather than a folder
+    #[must_use]
+    pub fn has_virtual_root(&self) -> bool {
+        self.roots().into_iter().any(|root| {
+            GitSource::from_root(root).is_some() || ArchiveKind::from_path(root).is_some()
+        })
+    }
+
+    /// The comparer that matches files: a registered one, or the built-in one for the comparison option
+    #[must_use]
+    pub fn file_comparer(&self) -> FileComparer {
+        self.custom_comparer
+            .clone()
+            .unwrap_or_else(|| builtin_comparer(self.comparer, self.name_key()))
+    }
+
+    /// How names are rewritten and folded before comparing them
+    #[must_use]
+    pub fn name_key(&self) -> NameKey {
+        NameKey {
+            transforms: self.name_transforms.clone(),
+            form: self.name_form(),
+        }
+    }
+
+    /// How names are folded before comparing them
+    #[must_use]
+    pub fn name_form(&self) -> NameForm {
+        NameForm {
+            case: self.name_case,
+            normalize: self.name_normalize,
+        }
+    }
+
+    /// Is anything listening for events of this kind?
+    #[must_use]
+    pub fn wants_event(&self, kind: EventKind) -> bool {
+        self.events.as_ref().is_some_and(|sink| sink.wants(kind))
+    }
+
+    /// Send an event of the given kind, if anything is listening for it. The event is only built if it will be sent
+    pub fn emit(&self, kind: EventKind, event: impl FnOnce() -> Event) {
+        if let Some(sink) = &self.events
+            && sink.wants(kind)
+        {
+            sink.event(event());
+        }
+    }
+
+    /// Report something that was worked around, to any event sink
+    pub fn warn(&self, side: Option<usize>, path: &Path, message: String) {
+        self.emit(EventKind::Warning, || Event::Warning {
+            side,
+            path: path.to_path_buf(),
+            message,
+        });
+    }
+
+    /// Does the comparison option hash file contents? If not, `FileData::contents` is left empty
+    #[must_use]
+    pub fn reads_contents(&self) -> bool {
+        !matches!(
+            self.comparer,
+            FileDataCompareOption::Name
+                | FileDataCompareOption::NameSize
+                | FileDataCompareOption::Stem
+        )
+    }
+
+    /// The comparison, with the hash algorithm for the comparisons it applies to. `GitBlob` always uses git's SHA-1
+    #[must_use]
+    pub fn comparison_label(&self) -> String {
+        match self.comparer {
+            FileDataCompareOption::Hash | FileDataCompareOption::QuickHash => {
+                format!("{:?} using {:?}", self.comparer, self.algorithm)
+            }
+            _ => format!("{:?}", self.comparer),
+        }
+    }
+
+    /// An ETA needs the total bytes up front, which is only known when fully hashing plain folders
+    #[must_use]
+    pub fn show_eta(&self) -> bool {
+        matches!(
+            self.comparer,
+            FileDataCompareOption::Hash | FileDataCompareOption::GitBlob
+        ) && !self.has_virtual_root()
+            && !self.descend_archives
+    }
+}
+
+/// Label for a folder in output, A, B, C... then numbers past Z
+#[must_use]
+pub fn folder_label(index: usize) -> String {
+    match u8::try_from(index) {
+        Ok(i) if i < 26 => char::from(b'A' + i).to_string(),
+        _ => format!("#{}", index + 1),
+    }
+}
+
+/// Hash a file using the given algorithm
+/// Returns a `HashKey`, which is a wrapper around the digest bytes
 /// # Errors
 /// Will return an error if the file cannot be opened or read
-pub fn hash_file<D: Digest>(filename: &str) -> anyhow::Result<Sha2Hash> {
+pub fn hash_file(
+    filename: &Path,
+    algorithm: HashAlgorithm,
+    io: IoStrategy,
+    counter: Option<&AtomicU64>,
+    cancel: &CancelToken,
+) -> anyhow::Result<HashKey> {
     let file = File::open(filename)?;
-    let mut reader = BufReader::new(file);
-    let mut buffer = [0u8; FILE_BUFFER_SIZE];
+    let size = file.metadata()?.len();
+    let mut hasher = FileHasher::new(algorithm);
+    hash_open_file(&mut hasher, file, size, io, counter, cancel)?;
+    Ok(hasher.finalize())
+}
 
-    let mut hasher = D::new();
-    loop {
-        let n = reader.read(&mut buffer)?;
-        if n == 0 {
-            break;
-        }
-        hasher.update(&buffer[..n]);
-    }
+/// Compute git's blob id for a file: SHA-1 of a `blob <size>` header and the contents
+/// # Errors
+/// Will return an error if the file cannot be opened or read
+pub fn hash_file_git_blob(
+    filename: &Path,
+    io: IoStrategy,
+    counter: Option<&AtomicU64>,
+    cancel: &CancelToken,
+) -> anyhow::Result<HashKey> {
+    let file = File::open(filename)?;
+    let size = file.metadata()?.len();
+    let mut hasher = git_blob_hasher(size);
+    hash_open_file(&mut hasher, file, size, io, counter, cancel)?;
+    Ok(hasher.finalize())
+}
 
-    let h = hasher.finalize();
+/// A SHA-1 hasher primed with git's blob header
+fn git_blob_hasher(size: u64) -> FileHasher {
+    let mut hasher = FileHasher::new(HashAlgorithm::Sha1);
+    hasher.update(format!("blob {size}\0"));
+    hasher
+}
 
-    Ok(Sha2Hash::new(&h))
+/// Feed an open file into the hasher, using the I/O strategy
+fn hash_open_file(
+    hasher: &mut FileHasher,
+    mut file: File,
+    size: u64,
+    io: IoStrategy,
+    counter: Option<&AtomicU64>,
+    cancel: &CancelToken,
+) -> anyhow::Result<()> {
+    let large = match io {
+        IoStrategy::Auto => size >= LARGE_IO_THRESHOLD,
+        IoStrategy::Buffered => false,
+        IoStrategy::Large => true,
+    };
+
+    if large {
+        // read straight into a big buffer, bypassing BufReader, so far fewer syscalls per GB
+        advise_sequential(&file);
+        let buffer_size = usize::try_from(size).map_or(LARGE_BUFFER_SIZE, |s| {
+            s.clamp(FILE_BUFFER_SIZE, LARGE_BUFFER_SIZE)
+        });
+        let mut storage = Vec::new();
+        let buffer = aligned_buffer(&mut storage, buffer_size);
+        hash_reader_with_buffer(hasher, &mut file, buffer, counter, cancel)
+    } else {
+        hash_reader(hasher, &mut BufReader::new(file), counter, cancel)
+    }
 }
 
-/// Hash a string slice and return a `Sha2Hash`
-pub fn hash_string<D: Digest>(text: &str) -> Sha2Hash {
-    let mut hasher = D::new();
-    hasher.update(text);
-    let h = hasher.finalize();
+/// Return a `size` byte window into `storage` that starts on a page boundary
+fn aligned_buffer(storage: &mut Vec<u8>, size: usize) -> &mut [u8] {
+    storage.resize(size + BUFFER_ALIGNMENT, 0);
+    let offset = match storage.as_ptr().align_offset(BUFFER_ALIGNMENT) {
+        o if o < BUFFER_ALIGNMENT => o,
+        _ => 0, // alignment not computable, an unaligned buffer still works
+    };
+    &mut storage[offset..offset + size]
+}
+
+/// Tell the kernel the whole file will be read once, front to back. Only a hint, so failures are ignored
+#[cfg(target_os = "linux")]
+fn advise_sequential(file: &File) {
+    use nix::fcntl::{PosixFadviseAdvice, posix_fadvise};
 
-    Sha2Hash::new(&h)
+    let _ = posix_fadvise(file, 0, 0, PosixFadviseAdvice::POSIX_FADV_SEQUENTIAL);
+    let _ = posix_fadvise(file, 0, 0, PosixFadviseAdvice::POSIX_FADV_NOREUSE);
 }
 
-/// Hash a string slice and a size and return a `Sha2Hash`
-pub fn hash_string_and_size<D: Digest>(text: &str, size: u64) -> Sha2Hash {
-    let mut hasher = D::new();
-    hasher.update(text);
+#[cfg(not(target_os = "linux"))]
+fn advise_sequential(_file: &File) {}
+
+/// Hash the first and last `quick_bytes` of a file, plus its size. Much faster than `hash_file` on large files,
+/// but only a probable match. Files no longer than 2 * `quick_bytes` are hashed in full
+/// # Errors
+/// Will return an error if the file cannot be opened or read
+pub fn hash_file_quick(
+    filename: &Path,
+    algorithm: HashAlgorithm,
+    quick_bytes: u64,
+    counter: Option<&AtomicU64>,
+    cancel: &CancelToken,
+) -> anyhow::Result<HashKey> {
+    let mut file = File::open(filename)?;
+    let size = file.metadata()?.len();
+
+    let mut hasher = FileHasher::new(algorithm);
     hasher.update(size.to_le_bytes());
-    let h = hasher.finalize();
 
-    Sha2Hash::new(&h)
+    if size <= quick_bytes.saturating_mul(2) {
+        // small file, the head and tail would overlap so just hash everything
+        hash_reader(&mut hasher, &mut BufReader::new(file), counter, cancel)?;
+    } else {
+        // head of the file
+        hash_reader(
+            &mut hasher,
+            &mut BufReader::new(&file).take(quick_bytes),
+            counter,
+            cancel,
+        )?;
+
+        // tail of the file
+        file.seek(SeekFrom::Start(size - quick_bytes))?;
+        hash_reader(
+            &mut hasher,
+            &mut BufReader::new(&file).take(quick_bytes),
+            counter,
+            cancel,
+        )?;
+    }
+
+    Ok(hasher.finalize())
 }
 
-pub fn parse_args() -> anyhow::Result<Config> {
-    let mut pargs = pico_args::Arguments::from_env();
-    let raw = pargs.contains(["-r", "--raw"]);
-    if !raw {
-        println!(
-            "Folder_comparer Rust, ver: {}, commit: {}",
-            VERSION.unwrap_or("?"),
-            GIT_VERSION
-        );
-        println!();
+/// Hash everything from a stream, such as an archive member. Gives the same key as `hash_file` on the same contents
+/// # Errors
+/// Will return an error if the stream cannot be read
+pub fn hash_stream(
+    reader: &mut impl Read,
+    algorithm: HashAlgorithm,
+    counter: Option<&AtomicU64>,
+    cancel: &CancelToken,
+) -> anyhow::Result<HashKey> {
+    let mut hasher = FileHasher::new(algorithm);
+    let mut buffer = vec![0u8; LARGE_BUFFER_SIZE];
+    hash_reader_with_buffer(&mut hasher, reader, &mut buffer, counter, cancel)?;
+    Ok(hasher.finalize())
+}
+
+/// Git's blob id for a stream of known size. Gives the same key as `hash_file_git_blob` on the same contents
+/// # Errors
+/// Will return an error if the stream cannot be read
+pub fn hash_stream_git_blob(
+    reader: &mut impl Read,
+    size: u64,
+    counter: Option<&AtomicU64>,
+    cancel: &CancelToken,
+) -> anyhow::Result<HashKey> {
+    let mut hasher = git_blob_hasher(size);
+    let mut buffer = vec![0u8; LARGE_BUFFER_SIZE];
+    hash_reader_with_buffer(&mut hasher, reader, &mut buffer, counter, cancel)?;
+    Ok(hasher.finalize())
+}
+
+/// Quick hash of a stream that can't seek, so the middle is read and discarded.
+/// Gives the same key as `hash_file_quick` on the same contents
+/// # Errors
+/// Will return an error if the stream cannot be read
+pub fn hash_stream_quick(
+    reader: &mut impl Read,
+    size: u64,
+    algorithm: HashAlgorithm,
+    quick_bytes: u64,
+    counter: Option<&AtomicU64>,
+    cancel: &CancelToken,
+) -> anyhow::Result<HashKey> {
+    let mut hasher = FileHasher::new(algorithm);
+    hasher.update(size.to_le_bytes());
+
+    if size <= quick_bytes.saturating_mul(2) {
+        hash_reader(&mut hasher, reader, counter, cancel)?;
+    } else {
+        hash_reader(
+            &mut hasher,
+            &mut reader.by_ref().take(quick_bytes),
+            counter,
+            cancel,
+        )?;
+        std::io::copy(
+            &mut reader.by_ref().take(size - quick_bytes * 2),
+            &mut std::io::sink(),
+        )?;
+        hash_reader(
+            &mut hasher,
+            &mut reader.by_ref().take(quick_bytes),
+            counter,
+            cancel,
+        )?;
     }
 
-    if pargs.contains(["-h", "--help"]) {
-        println!("{HELP}");
-        return Err(anyhow::anyhow!("Exiting early"));
+    Ok(hasher.finalize())
+}
+
+/// Feed everything from a reader into the hasher, adding the bytes read to the optional progress counter
+fn hash_reader(
+    hasher: &mut FileHasher,
+    reader: &mut impl Read,
+    counter: Option<&AtomicU64>,
+    cancel: &CancelToken,
+) -> anyhow::Result<()> {
+    let mut buffer = [0u8; FILE_BUFFER_SIZE];
+    hash_reader_with_buffer(hasher, reader, &mut buffer, counter, cancel)
+}
+
+/// Feed everything from a reader into the hasher, using the given buffer
+fn hash_reader_with_buffer(
+    hasher: &mut FileHasher,
+    reader: &mut impl Read,
+    buffer: &mut [u8],
+    counter: Option<&AtomicU64>,
+    cancel: &CancelToken,
+) -> anyhow::Result<()> {
+    loop {
+        // checked per buffer, so a huge file doesn't delay cancellation
+        cancel.check()?;
+        let n = reader.read(buffer)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+        if let Some(counter) = counter {
+            counter.fetch_add(n as u64, Ordering::Relaxed);
+        }
     }
+    Ok(())
+}
 
-    let path1: String = pargs.value_from_str(["-a", "--foldera"])?;
-    let path2: String = pargs.value_from_str(["-b", "--folderb"])?;
-    let comparer_str: Option<String> = pargs.opt_value_from_str(["-c", "--comparison"])?;
-    let comparer_res = parse_comparer(comparer_str.as_ref());
+/// Make a path from raw bytes, such as a git tree entry or a line of a file list
+#[cfg(unix)]
+#[must_use]
+pub fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+    PathBuf::from(std::ffi::OsStr::from_bytes(bytes))
+}
 
-    // additional validation
+/// Make a path from raw bytes, assumed to be UTF-8 where paths must be Unicode
+#[cfg(not(unix))]
+pub fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(bytes).into_owned())
+}
 
-    if comparer_res.is_err() {
-        return Err(anyhow::anyhow!(
-            "Comparison should be Name, NameSize or Hash"
-        ));
+/// A path relative to its root. Archive and git members are shown as `root!/member`, so that prefix is removed
+#[must_use]
+pub fn relative_path(root: &Path, path: &Path) -> PathBuf {
+    if let Ok(relative) = path.strip_prefix(root) {
+        return relative.to_path_buf();
     }
 
-    // package the config options, so they can be easily passed around
+    let bytes = path.as_os_str().as_encoded_bytes();
+    let member = bytes
+        .strip_prefix(root.as_os_str().as_encoded_bytes())
+        .and_then(|rest| rest.strip_prefix(b"!/"))
+        .unwrap_or(bytes);
+    path_from_bytes(member)
+}
+
+/// Format a count with thousands separators, eg 1,234
+#[must_use]
+pub fn format_count(count: usize) -> String {
+    let digits = count.to_string();
+    let mut formatted = String::with_capacity(digits.len() + digits.len() / 3);
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            formatted.push(',');
+        }
+        formatted.push(digit);
+    }
+    formatted
+}
 
-    let config = Config {
-        folder1: Path::new(&path1).canonicalize()?,
-        folder2: Path::new(&path2).canonicalize()?,
-        comparer: comparer_res.unwrap(),
-        raw,
-        first_only: pargs.contains(["-f", "--first-only"]),
-        one_thread: pargs.contains(["-o", "--one-thread"]),
+/// Format a time in UTC, eg `2024-05-01 12:34:56.123456789`
+#[must_use]
+pub fn format_time(time: SystemTime) -> String {
+    let (seconds, nanos) = match time.duration_since(UNIX_EPOCH) {
+        Ok(after) => (
+            i64::try_from(after.as_secs()).unwrap_or(i64::MAX),
+            after.subsec_nanos(),
+        ),
+        Err(e) => {
+            // before 1970, so count back to the whole second below
+            let before = e.duration();
+            let seconds = -i64::try_from(before.as_secs()).unwrap_or(i64::MAX);
+            match before.subsec_nanos() {
+                0 => (seconds, 0),
+                nanos => (seconds - 1, 1_000_000_000 - nanos),
+            }
+        }
     };
 
-    // Check for unused arguments, and error out if there are any
-    let unused = pargs.finish();
-    if !unused.is_empty() {
-        return Err(anyhow::anyhow!("Unused arguments: {unused:?}"));
+    let (days, of_day) = (seconds.div_euclid(86_400), seconds.rem_euclid(86_400));
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02}.{nanos:09}",
+        of_day / 3600,
+        of_day / 60 % 60,
+        of_day % 60
+    )
+}
+
+/// Year, month and day of a count of days since 1970-01-01, from Howard Hinnant's `civil_from_days`
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Format a byte count for humans, eg 5.6 GB
+#[allow(clippy::cast_precision_loss)]
+#[must_use]
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["KB", "MB", "GB", "TB", "PB"];
+
+    if bytes < 1024 {
+        return format!("{bytes} B");
     }
 
-    Ok(config)
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{value:.1} {}", UNITS[unit])
 }
 
-/// Scan A and return a vector of the records not found in B
-pub fn hashmap_difference<'a>(
-    a: &'a HashMap<Sha2Hash, FilePath>,
-    b: &'a HashMap<Sha2Hash, FilePath>,
-) -> Vec<&'a FilePath> {
-    let mut diff = Vec::new();
-    for (k, v) in a {
-        if !b.contains_key(k) {
-            diff.push(v);
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_config_quick_hashes_contents() {
+        let config = Config::default();
+        assert_eq!(config.quick_bytes, DEFAULT_QUICK_KIB * 1024);
+
+        // same size, different contents, so only a key that reads the contents tells them apart
+        let folder =
+            std::env::temp_dir().join(format!("folder_compare_utils_{}", std::process::id()));
+        std::fs::create_dir_all(&folder).unwrap();
+        let (a, b) = (folder.join("a"), folder.join("b"));
+        std::fs::write(&a, "first").unwrap();
+        std::fs::write(&b, "other").unwrap();
+
+        let key = |path: &Path| {
+            hash_file_quick(
+                path,
+                config.algorithm,
+                config.quick_bytes,
+                None,
+                &config.cancel,
+            )
+            .unwrap()
+        };
+        let (key_a, key_b) = (key(&a), key(&b));
+        let _ = std::fs::remove_dir_all(&folder);
+        assert_ne!(key_a, key_b);
     }
-    diff
 }
@@ -0,0 +1,63 @@
+#!/usr/bin/env bash
+# Benchmark the --io strategies on folders of random files, one size class at a time, for the table in
+# Benchmarks.md. Each side holds the same files, so everything is hashed and nothing differs.
+#
+#   ./bench_io.sh [folder_compare binary] [work folder]
+#
+# RUNS sets how many times each strategy is timed (default 9), and the median is printed in milliseconds.
+# The first, untimed, run of each size warms the page cache.
+set -euo pipefail
+
+BIN=${1:-../Rust/target/release/folder_compare}
+WORK=${2:-${TMPDIR:-/tmp}/folder_compare_bench}
+RUNS=${RUNS:-9}
+
+# size in KiB, files per side
+SIZES=(
+    "4 2000"
+    "16 1500"
+    "64 1000"
+    "1024 200"
+    "16384 16"
+    "262144 2"
+)
+
+make_side() {
+    local folder=$1 kib=$2 count=$3
+    mkdir -p "$folder"
+    for ((i = 0; i < count; i++)); do
+        head -c "$((kib * 1024))" /dev/urandom >"$folder/$i.bin"
+    done
+}
+
+median_ms() {
+    local io=$1 folder=$2 times=()
+    for ((run = 0; run < RUNS; run++)); do
+        local start end
+        start=$(date +%s%N)
+        "$BIN" -a "$folder/a" -b "$folder/b" -c hash -g blake3 -r -i "$io" >/dev/null
+        end=$(date +%s%N)
+        times+=($(((end - start) / 1000000)))
+    done
+    printf '%s\n' "${times[@]}" | sort -n | sed -n "$((RUNS / 2 + 1))p"
+}
+
+echo "| File size | Files per side | Buffered | Large | Auto |"
+echo "|-----------|----------------|----------|-------|------|"
+for entry in "${SIZES[@]}"; do
+    read -r kib count <<<"$entry"
+    folder="$WORK/$kib"
+    if [[ ! -d $folder/b ]]; then
+        rm -rf "$folder"
+        make_side "$folder/a" "$kib" "$count"
+        cp -r "$folder/a" "$folder/b"
+    fi
+
+    "$BIN" -a "$folder/a" -b "$folder/b" -c hash -g blake3 -r >/dev/null
+    if ((kib >= 1024)); then
+        label="$((kib / 1024)) MiB"
+    else
+        label="$kib KiB"
+    fi
+    echo "| $label | $count | $(median_ms buffered "$folder") | $(median_ms large "$folder") | $(median_ms auto "$folder") |"
+done